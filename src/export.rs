@@ -0,0 +1,213 @@
+use std::{error::Error, hash::Hash, io::Write, str::FromStr};
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use tracing::{debug, instrument};
+
+use crate::{mailbox::{Address, DateDisplay}, threading::ThreadForest, Email};
+
+#[derive(Debug, PartialEq, strum::Display)]
+pub enum ExportError {
+    UnknownFieldError,
+    WriteError,
+}
+
+impl Error for ExportError { }
+
+/// Field of an exported email, named in snake case in JSON keys and CSV headers.
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ExportField {
+    Id,
+    From,
+    FromAddress,
+    Date,
+    Subject,
+    To,
+    Cc,
+    Bcc,
+    ReplyTo,
+    MessageId,
+    InReplyTo,
+    References,
+    ListId,
+    BodyText,
+    BodyHtml,
+    TextRepaired,
+    Attachments,
+    ThreadId,
+}
+
+impl ExportField {
+
+    pub const ALL: [ExportField; 18] = [ExportField::Id, ExportField::From, ExportField::FromAddress, ExportField::Date,
+        ExportField::Subject, ExportField::To, ExportField::Cc, ExportField::Bcc, ExportField::ReplyTo, ExportField::MessageId,
+        ExportField::InReplyTo, ExportField::References, ExportField::ListId, ExportField::BodyText, ExportField::BodyHtml,
+        ExportField::TextRepaired, ExportField::Attachments, ExportField::ThreadId];
+
+    /// Fields of a comma separated list of names, e.g. `id,date,subject`.
+    pub fn parse_list(names: &str) -> Result<Vec<Self>, ExportError> {
+        names.split(',')
+            .map(|name| ExportField::from_str(name.trim()).or(Err(ExportError::UnknownFieldError)))
+            .collect()
+    }
+
+}
+
+/// Serialisation of emails to JSON Lines or CSV, one email per line with the selected fields in order.
+/// Emails are written as they are read, so that a whole mailbox can be exported from `MailStorageRepository::emails`.
+pub struct EmailExporter<'a, EmailId> {
+    fields: Vec<ExportField>,
+    /// Threads of the mailbox, the `thread_id` field being null without them.
+    threads: Option<&'a ThreadForest<EmailId>>,
+    /// Zone of the RFC 3339 `date` field, sender-local by default.
+    date_display: DateDisplay,
+}
+
+impl<'a, EmailId: Serialize + Clone + Eq + Hash> EmailExporter<'a, EmailId> {
+
+    pub fn new(fields: Vec<ExportField>) -> Self {
+        EmailExporter { fields, threads: None, date_display: DateDisplay::default() }
+    }
+
+    pub fn with_threads(mut self, threads: &'a ThreadForest<EmailId>) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn with_date_display(mut self, date_display: DateDisplay) -> Self {
+        self.date_display = date_display;
+        self
+    }
+
+    /// Write one JSON object per email and line, return the number of emails written.
+    #[instrument(skip_all)]
+    pub fn write_jsonl<W: Write>(&self, emails: impl IntoIterator<Item = Email<EmailId>>, mut writer: W) -> Result<usize, ExportError> {
+        let mut count = 0;
+        for email in emails {
+            let record: Map<String, Value> = self.fields.iter()
+                .map(|field| (field.to_string(), self.value(&email, *field)))
+                .collect();
+            serde_json::to_writer(&mut writer, &record).or(Err(ExportError::WriteError))?;
+            writeln!(writer).or(Err(ExportError::WriteError))?;
+            count += 1;
+        }
+        writer.flush().or(Err(ExportError::WriteError))?;
+        debug!("Exported {count} emails to JSON Lines");
+        Ok(count)
+    }
+
+    /// Write a header record with the field names then one record per email, return the number of emails written.
+    /// Address lists are written as in headers, attachments as a JSON array.
+    #[instrument(skip_all)]
+    pub fn write_csv<W: Write>(&self, emails: impl IntoIterator<Item = Email<EmailId>>, writer: W) -> Result<usize, ExportError> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        csv_writer.write_record(self.fields.iter().map(|field| field.to_string()))
+            .or(Err(ExportError::WriteError))?;
+        let mut count = 0;
+        for email in emails {
+            csv_writer.write_record(self.fields.iter().map(|field| self.cell(&email, *field)))
+                .or(Err(ExportError::WriteError))?;
+            count += 1;
+        }
+        csv_writer.flush().or(Err(ExportError::WriteError))?;
+        debug!("Exported {count} emails to CSV");
+        Ok(count)
+    }
+
+    fn value(&self, email: &Email<EmailId>, field: ExportField) -> Value {
+        match field {
+            ExportField::Id => json!(email.id),
+            ExportField::From => json!(email.from),
+            ExportField::FromAddress => json!(email.from_address),
+            ExportField::Date => json!(self.date_display.convert(&email.sender_datetime).to_rfc3339()),
+            ExportField::Subject => json!(email.subject),
+            ExportField::To => json!(email.to),
+            ExportField::Cc => json!(email.cc),
+            ExportField::Bcc => json!(email.bcc),
+            ExportField::ReplyTo => json!(email.reply_to),
+            ExportField::MessageId => json!(email.message_id),
+            ExportField::InReplyTo => json!(email.in_reply_to),
+            ExportField::References => json!(email.references),
+            ExportField::ListId => json!(email.list_id),
+            ExportField::BodyText => json!(email.body_text),
+            ExportField::BodyHtml => json!(email.body_html),
+            ExportField::TextRepaired => json!(email.text_repaired),
+            ExportField::Attachments => json!(email.attachments),
+            ExportField::ThreadId => json!(self.threads.and_then(|threads| threads.thread_index(&email.id))),
+        }
+    }
+
+    fn cell(&self, email: &Email<EmailId>, field: ExportField) -> String {
+        let addresses = |addresses: &[Address]| addresses.iter().map(|address| address.to_string()).collect::<Vec<_>>().join(", ");
+        match field {
+            ExportField::FromAddress => email.from_address.as_ref().map(|address| address.to_string()).unwrap_or_default(),
+            ExportField::To => addresses(&email.to),
+            ExportField::Cc => addresses(&email.cc),
+            ExportField::Bcc => addresses(&email.bcc),
+            ExportField::ReplyTo => addresses(&email.reply_to),
+            _ => match self.value(email, field) {
+                Value::Null => String::new(),
+                Value::String(value) => value,
+                value => value.to_string(),
+            },
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{storage::file::MboxFile, MailStorageRepository};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_field_list() {
+        assert_eq!(vec![ExportField::Id, ExportField::ReplyTo, ExportField::ThreadId], ExportField::parse_list("id, reply_to,thread_id").unwrap());
+        assert!(ExportField::parse_list("id,size").is_err_and(|e| e == ExportError::UnknownFieldError));
+        assert_eq!(ExportField::ALL.to_vec(), ExportField::parse_list(&ExportField::ALL.map(|field| field.to_string()).join(",")).unwrap());
+    }
+
+    #[test]
+    fn test_write_jsonl() {
+        let mbox = MboxFile::new("datasets/dev_apisix_apache_org.mbox").unwrap();
+        let threads = ThreadForest::build(&mbox);
+        let exporter = EmailExporter::new(ExportField::parse_list("id,subject,to,attachments,thread_id").unwrap()).with_threads(&threads);
+        let mut output = vec![];
+        assert_eq!(mbox.count_emails().unwrap(), exporter.write_jsonl(mbox.emails(), &mut output).unwrap());
+        let lines: Vec<Value> = output.split(|&c| c == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(mbox.count_emails().unwrap(), lines.len());
+        assert_eq!(json!(1), lines[1]["id"]);
+        assert_eq!("Re: [VOTE] Apache apisix-ingress-controller release version 2.0.0-rc3", lines[1]["subject"]);
+        assert_eq!("dev@apisix.apache.org", lines[1]["to"][0]["address"]);
+        assert_eq!(json!([]), lines[1]["attachments"]);
+        assert_eq!(lines[0]["thread_id"], lines[1]["thread_id"]);
+        assert!(String::from_utf8(output).unwrap().starts_with("{\"id\":0,\"subject\":"));
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let mbox = MboxFile::new("datasets/test_lex.mbox").unwrap();
+        let exporter = EmailExporter::new(vec![ExportField::Id, ExportField::Subject, ExportField::ThreadId, ExportField::BodyText, ExportField::Date]);
+        let mut output = vec![];
+        assert_eq!(3, exporter.write_csv(mbox.emails(), &mut output).unwrap());
+        let mut reader = csv::Reader::from_reader(output.as_slice());
+        assert_eq!(vec!["id", "subject", "thread_id", "body_text", "date"], reader.headers().unwrap().iter().collect::<Vec<_>>());
+        let records: Vec<csv::StringRecord> = reader.records().map(|record| record.unwrap()).collect();
+        assert_eq!(3, records.len());
+        assert_eq!("0", &records[0][0]);
+        assert_eq!(mbox.get_email(&0).unwrap().subject.unwrap(), &records[0][1]);
+        assert_eq!("", &records[0][2]);
+        assert_eq!(mbox.get_email(&0).unwrap().body_text.unwrap(), &records[0][3]);
+        assert_eq!("2025-08-04T09:22:15+08:00", &records[0][4]);
+
+        let exporter = EmailExporter::new(vec![ExportField::Date]).with_date_display(DateDisplay::Utc);
+        let mut output = vec![];
+        exporter.write_csv(mbox.emails().take(1), &mut output).unwrap();
+        assert_eq!("date\n2025-08-04T01:22:15+00:00\n", String::from_utf8(output).unwrap());
+    }
+}
@@ -1,7 +1,9 @@
 pub mod mailbox;
 pub mod embedding;
+pub mod export;
 pub mod search;
 pub mod storage;
+pub mod threading;
 
 pub use mailbox::Email;
 pub use search::MailSearchRepository;
@@ -1,12 +1,16 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, fmt::{Debug, Display}, hash::Hash};
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use serde::Serialize;
 use tracing::{error, instrument};
 
-use crate::{embedding::{local::{InternalEmbedder, InternalEmbedderModelPool, InternalEmbedderPool}, Embedder}, search::memory_cosinus::MemoryCosinus, storage::file::MboxFile, MailSearchRepository, MailStorageRepository};
+use crate::{embedding::{local::{InternalEmbedder, InternalEmbedderModelPool, InternalEmbedderPool}, Embedder}, search::memory_cosinus::MemoryCosinus, storage::{eml::EmlDirectory, file::{MboxFile, Refresh}, format::MboxFormat, maildir::Maildir, message::ParseMode, view::EmailView, writer, MailboxError}, MailSearchRepository, MailStorageRepository};
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
 
+/// Number of emails embedded and added to the search index at once.
+const INDEX_BUFFER_SIZE: usize = 600;
+
 #[derive(Debug, strum::Display)]
 pub enum MailboxServiceError {
     InitError,
@@ -19,21 +23,126 @@ impl Error for MailboxServiceError { }
 pub struct Email<EmailId> {
     pub id: EmailId,
     pub from: String,
+    /// First mailbox of the `From` header.
+    pub from_address: Option<Address>,
     pub datetime: DateTime<Utc>,
-    pub subject: String,
+    /// Same instant as `datetime`, with the offset written by the sender.
+    pub sender_datetime: DateTime<FixedOffset>,
+    /// Missing only for emails of mailboxes parsed in lenient mode.
+    pub subject: Option<String>,
+    pub to: Vec<Address>,
+    pub cc: Vec<Address>,
+    pub bcc: Vec<Address>,
+    pub reply_to: Vec<Address>,
+    pub message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Option<String>,
+    pub list_id: Option<String>,
     pub body_text: Option<String>,
-    pub body_html: Option<String>
+    pub body_html: Option<String>,
+    /// Error decoding a body left `None`, e.g. `UnknownTransferEncodingError` for an unknown declared encoding.
+    pub body_error: Option<MailboxError>,
+    /// Set when a body was not valid in its declared charset and had to be repaired.
+    pub text_repaired: bool,
+    pub attachments: Vec<Attachment>,
 }
 
-impl<EmailId: Display> Display for Email<EmailId> {
+/// Mailbox of an address header : optional display name and addr-spec.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Address {
+    pub name: Option<String>,
+    /// Address as written in the header, `local-part@domain`.
+    pub address: String,
+    /// Name of the group the address is listed in, if any.
+    pub group: Option<String>,
+}
+
+impl Address {
+
+    /// Lower-cased address, to compare or group senders.
+    pub fn normalized(&self) -> String {
+        self.address.to_lowercase()
+    }
+
+}
+
+impl Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}, {}, {}, {}, {}, {} ", &self.id, &self.from, &self.datetime, &self.subject,
-            self.body_text.as_ref().unwrap_or(&"none".to_string()),
-            self.body_html.as_ref().unwrap_or(&"none".to_string())
+        match &self.name {
+            Some(name) => write!(f, "{name} <{}>", self.address),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
+
+/// Non body part of an email. Its content is available from `MailStorageRepository::attachment_content`
+/// with the position of the attachment in `Email::attachments`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Attachment {
+    pub filename: Option<String>,
+    pub mime_type: String,
+    /// Decoded size in bytes, an upper bound for quoted-printable or uuencoded contents.
+    pub size: usize,
+    pub content_id: Option<String>,
+}
+
+/// Time zone in which the dates of emails are rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum DateDisplay {
+    /// Local time of the sender, with the offset of the `Date` header.
+    #[default]
+    SenderLocal,
+    Utc,
+    /// Local time of the machine running the viewer.
+    ViewerLocal,
+}
+
+impl DateDisplay {
+
+    /// Same instant as `datetime` in the zone of the display.
+    pub fn convert(&self, datetime: &DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self {
+            DateDisplay::SenderLocal => *datetime,
+            DateDisplay::Utc => datetime.to_utc().fixed_offset(),
+            DateDisplay::ViewerLocal => datetime.with_timezone(&Local).fixed_offset(),
+        }
+    }
+
+}
+
+impl<EmailId> Email<EmailId> {
+
+    /// Displayable email with its date rendered in the zone of `date_display`.
+    pub fn display(&self, date_display: DateDisplay) -> EmailDisplay<'_, EmailId> {
+        EmailDisplay { email: self, date_display }
+    }
+
+}
+
+/// Email rendered on one line by `Email::display`.
+pub struct EmailDisplay<'a, EmailId> {
+    email: &'a Email<EmailId>,
+    date_display: DateDisplay,
+}
+
+impl<EmailId: Display> Display for EmailDisplay<'_, EmailId> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let email = self.email;
+        write!(f, "{}, {}, {}, {}, {}, {} ", &email.id, &email.from, self.date_display.convert(&email.sender_datetime),
+            email.subject.as_deref().unwrap_or_default(),
+            email.body_text.as_ref().unwrap_or(&"none".to_string()),
+            email.body_html.as_ref().unwrap_or(&"none".to_string())
         )
     }
 }
 
+impl<EmailId: Display> Display for Email<EmailId> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(DateDisplay::default()).fmt(f)
+    }
+}
+
 #[derive(Debug)]
 pub struct MailboxService<T:MailStorageRepository> {
     storage_repository: T,
@@ -45,37 +154,40 @@ impl <T:MailStorageRepository> MailboxService<T> {
 
     #[instrument(skip_all)]
     pub fn index_emails(&mut self) {
-        const INDEX_BUFFER_SIZE: usize = 600;
-
-        let mut emails_iterator = self.storage_repository.emails();
+        let mut views_iterator = self.storage_repository.email_views();
         loop {
-            let mut buf: Vec<Email<<T as MailStorageRepository>::EmailId>> = Vec::with_capacity(INDEX_BUFFER_SIZE);
-            for _ in 0..INDEX_BUFFER_SIZE {
-                if let Some(email) = emails_iterator.next() {
-                    buf.push(email);
-                } else {
-                    break;
-                }
-            }
+            let buf: Vec<EmailView<<T as MailStorageRepository>::EmailId>> = views_iterator.by_ref().take(INDEX_BUFFER_SIZE).collect();
             if buf.is_empty() {
                 break;
             }
+            Self::index_buffer(self.embedder.as_ref(), self.search_repository.as_mut(), buf);
+        }
+    }
 
-            let (mut ids, bodies) = self.emails_to_ids_and_bodies_if_body_exists(buf);
-            let bodies_str:Vec<&str> = bodies.iter().map(|body| body.as_str()).collect();
+    /// Embed and index the text body of the views, or their HTML body without one, skipping views without body.
+    fn index_buffer(embedder: &dyn Embedder,
+            search_repository: &mut dyn MailSearchRepository<EmailId = <T as MailStorageRepository>::EmailId>,
+            buf: Vec<EmailView<<T as MailStorageRepository>::EmailId>>) {
+        let buf: Vec<_> = buf.into_iter().filter(|view| Self::indexed_body(view).is_some()).collect();
+        let bodies: Vec<&str> = buf.iter().filter_map(Self::indexed_body).collect();
+        let embedded = embedder.embed(&bodies);
+        let mut ids: Vec<_> = buf.into_iter().map(EmailView::into_id).collect();
 
-            if let Ok(mut vectors) = self.embedder.embed(&bodies_str) && vectors.len() == ids.len() {
-                while let Some(id) = ids.pop() && let Some(vector) = vectors.pop() {
-                    if self.search_repository.index(id, vector).is_err() {
-                        error!("Error when store search embedding of email");
-                    }
+        if let Ok(mut vectors) = embedded && vectors.len() == ids.len() {
+            while let Some(id) = ids.pop() && let Some(vector) = vectors.pop() {
+                if search_repository.index(id, vector).is_err() {
+                    error!("Error when store search embedding of email");
                 }
-            } else {
-                error!("Error when calculate embeddind of emails : {}", ids.iter().map(|id| id.to_string()).collect::<String>());
             }
+        } else {
+            error!("Error when calculate embeddind of emails : {}", ids.iter().map(|id| id.to_string()).collect::<String>());
         }
     }
 
+    fn indexed_body<'v>(view: &'v EmailView<<T as MailStorageRepository>::EmailId>) -> Option<&'v str> {
+        view.body_text().or_else(|| view.body_html())
+    }
+
     #[instrument(skip_all, fields(user_search_input=%search_request))]
     pub fn search_email(&self, search_request: &str) -> Result<Vec<(f32, Email<<T as MailStorageRepository>::EmailId>)>> {
         const LIMIT_SEARCH_RESULTS: usize = 5;
@@ -88,39 +200,97 @@ impl <T:MailStorageRepository> MailboxService<T> {
         Ok(res)
     }
 
-    fn emails_to_ids_and_bodies_if_body_exists(&self, buf: Vec<Email<<T as MailStorageRepository>::EmailId>>) -> (Vec<<T as MailStorageRepository>::EmailId>, Vec<String>) {
-        buf.into_iter()
-            .filter_map(|email|
-                if let Some(body_text) = email.body_text {
-                    Some((email.id, body_text))
-                } else if let Some(body_html) = email.body_html  {
-                    Some((email.id, body_html))
-                } else {
-                    None
-                }
-            )
-            .unzip()
+    /// Write the emails `ids`, e.g. search results, byte-exact to a new mbox file in `format`.
+    pub fn export_emails<'a>(&self, ids: impl IntoIterator<Item = &'a <T as MailStorageRepository>::EmailId>, file_path: &str, format: MboxFormat) -> Result<usize>
+            where <T as MailStorageRepository>::EmailId: 'a {
+        Ok(writer::export(&self.storage_repository, ids, file_path, format)?)
     }
 
 }
 
 
 
+impl MailboxService<MboxFile> {
+
+    /// Parse the emails appended to the mailbox file and index only those, return their number.
+    /// A rewritten mailbox is indexed again from scratch, all its emails being counted.
+    #[instrument(skip_all)]
+    pub fn refresh_emails(&mut self) -> Result<usize> {
+        let ids = match self.storage_repository.refresh()? {
+            Refresh::Appended(ids) => ids,
+            Refresh::Rewritten => {
+                self.search_repository.clear();
+                self.index_emails();
+                return Ok(self.storage_repository.count_emails()?);
+            }
+        };
+        for ids_chunk in ids.chunks(INDEX_BUFFER_SIZE) {
+            let buf = ids_chunk.iter()
+                .filter_map(|id| self.storage_repository.email_view(id).ok())
+                .collect();
+            Self::index_buffer(self.embedder.as_ref(), self.search_repository.as_mut(), buf);
+        }
+        Ok(ids.len())
+    }
+
+}
+
+impl<T: MailStorageRepository> MailboxService<T> where <T as MailStorageRepository>::EmailId: Hash + Eq + Clone + Debug + 'static {
+
+    /// Service over any storage, with the internal embedder and an in memory search index.
+    pub fn with_storage(storage_repository: T) -> std::result::Result<Self, MailboxServiceError> {
+        // if let Ok(embedder) = time_it!("Init internal embedder", { InternalEmbedder::new() }) {
+        let embedder = InternalEmbedderModelPool::new(4).or(Err(MailboxServiceError::InitError))?;
+        Ok(MailboxService {
+            storage_repository,
+            search_repository: Box::new(MemoryCosinus::new()),
+            embedder: Box::new(embedder)
+        })
+    }
+
+}
+
 impl<'a> TryFrom<&str> for MailboxService<MboxFile> {
     type Error = MailboxServiceError;
 
     fn try_from(source: &str) -> std::result::Result<Self, Self::Error> {
-        // if let Ok(embedder) = time_it!("Init internal embedder", { InternalEmbedder::new() }) {
-        if let Ok(embedder) = InternalEmbedderModelPool::new(4) {
-            MboxFile::new(source)
-                .map(|s| MailboxService {
-                    storage_repository: s,
-                    search_repository: Box::new(MemoryCosinus::new()),
-                    embedder: Box::new(embedder)
-                }).or(Err(MailboxServiceError::InitError))
-        } else {
-            Err(MailboxServiceError::InitError)
-        }
+        MboxFile::new(source)
+            .or(Err(MailboxServiceError::InitError))
+            .and_then(MailboxService::with_storage)
+    }
+
+}
+
+impl TryFrom<(&str, ParseMode)> for MailboxService<MboxFile> {
+    type Error = MailboxServiceError;
+
+    /// Open the mbox `source`, keeping messages with missing headers in lenient mode.
+    fn try_from((source, mode): (&str, ParseMode)) -> std::result::Result<Self, Self::Error> {
+        MboxFile::with_mode(source, None, mode)
+            .or(Err(MailboxServiceError::InitError))
+            .and_then(MailboxService::with_storage)
+    }
+
+}
+
+impl TryFrom<&str> for MailboxService<Maildir> {
+    type Error = MailboxServiceError;
+
+    fn try_from(source: &str) -> std::result::Result<Self, Self::Error> {
+        Maildir::new(source)
+            .or(Err(MailboxServiceError::InitError))
+            .and_then(MailboxService::with_storage)
+    }
+
+}
+
+impl TryFrom<&str> for MailboxService<EmlDirectory> {
+    type Error = MailboxServiceError;
+
+    fn try_from(source: &str) -> std::result::Result<Self, Self::Error> {
+        EmlDirectory::new(source)
+            .or(Err(MailboxServiceError::InitError))
+            .and_then(MailboxService::with_storage)
     }
 
 }
@@ -1,7 +1,7 @@
 
-use std::env;
+use std::{env, path::Path};
 
-use mbox_viewer::{mailbox::MailboxService, storage::file::MboxFile};
+use mbox_viewer::{mailbox::{DateDisplay, MailboxService}, storage::{eml::EmlDirectory, file::MboxFile, maildir::{is_maildir, Maildir}, message::ParseMode}, MailStorageRepository};
 
 
 fn main() {
@@ -12,13 +12,31 @@ fn main() {
     let args: Vec<String> = env::args().collect();
     let search_request = &args[1];
     let mbox_file_path = &args[2];
-    let mut mailbox:MailboxService<MboxFile> = mbox_file_path.as_str().try_into()
-            .expect("Error initializing mailbox service");
+    // optional zone of the dates : sender_local (default), utc or viewer_local
+    let date_display: DateDisplay = args.get(3).and_then(|value| value.parse().ok()).unwrap_or_default();
+    // optional handling of mbox messages missing headers : strict (default) or lenient
+    let parse_mode: ParseMode = args.get(4).and_then(|value| value.parse().ok()).unwrap_or_default();
+    if is_maildir(Path::new(mbox_file_path)) {
+        let mailbox: MailboxService<Maildir> = mbox_file_path.as_str().try_into()
+                .expect("Error initializing mailbox service");
+        search(mailbox, search_request, date_display);
+    } else if Path::new(mbox_file_path).is_dir() {
+        let mailbox: MailboxService<EmlDirectory> = mbox_file_path.as_str().try_into()
+                .expect("Error initializing mailbox service");
+        search(mailbox, search_request, date_display);
+    } else {
+        let mailbox: MailboxService<MboxFile> = (mbox_file_path.as_str(), parse_mode).try_into()
+                .expect("Error initializing mailbox service");
+        search(mailbox, search_request, date_display);
+    }
+}
+
+fn search<T: MailStorageRepository>(mut mailbox: MailboxService<T>, search_request: &str, date_display: DateDisplay) {
     mailbox.index_emails();
     if let Ok(search_results) = mailbox.search_email(search_request) {
         for (score, email) in &search_results {
             println!("Score : {score}");
-            println!("{email}");
+            println!("{}", email.display(date_display));
         }
     }
 }
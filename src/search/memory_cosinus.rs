@@ -37,6 +37,10 @@ impl <T: Hash + Eq + PartialOrd + Clone + Debug> MailSearchRepository for Memory
         Ok(())
     }
 
+    fn clear(&mut self) {
+        self.vectors.clear();
+    }
+
     #[instrument(skip_all, fields(nb_resultats = %nb_results))]
     fn search(&self, ask: &[f32], nb_results: usize) -> Result<Vec<SearchResult<Self::EmailId>>, super::SearchError> {
         let mut scores = BinaryHeap::with_capacity(nb_results);
@@ -23,6 +23,9 @@ pub trait MailSearchRepository: Debug {
 
     fn search(&self, ask: &[f32], nb_results: usize) -> Result<Vec<SearchResult<Self::EmailId>>, SearchError>;
 
+    /// Remove all indexed emails, e.g. before indexing a rewritten mailbox again.
+    fn clear(&mut self);
+
 }
 
 impl<T: PartialOrd> Eq for SearchResult<T> {}
@@ -0,0 +1,226 @@
+use rfc2047_decoder::{Decoder, RecoverStrategy};
+
+use crate::mailbox::Address;
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// Atom, dot-atom or domain literal.
+    Word(String),
+    /// Quoted string, unescaped.
+    Quoted(String),
+    Comment(String),
+    Special(char),
+}
+
+/// Parse an address list header value (RFC 5322 section 3.4) : mailboxes with or without display name,
+/// groups, comments and obsolete routes or spacing. Encoded words in display names are decoded.
+/// Parsing is lenient, entries without any address are skipped.
+pub fn parse_address_list(value: &str) -> Vec<Address> {
+    let tokens = tokenize(value);
+    let mut addresses = vec![];
+    let mut group: Option<String> = None;
+    let mut pending: Vec<&Token> = vec![];
+    // set once the angle address of the current mailbox is read, the rest up to the separator is ignored
+    let mut angle_read = false;
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Special('<') => {
+                let mut angle: Vec<&Token> = tokens.by_ref().take_while(|token| **token != Token::Special('>')).collect();
+                // obsolete route `<@relay1,@relay2:user@domain>`
+                if let Some(route_end) = angle.iter().position(|token| **token == Token::Special(':')) {
+                    angle.drain(..=route_end);
+                }
+                let name = phrase(&pending);
+                pending.clear();
+                if let Some(address) = addr_spec(&angle) {
+                    addresses.push(Address { name, address, group: group.clone() });
+                }
+                angle_read = true;
+            }
+            Token::Special(':') if group.is_none() && !angle_read => {
+                group = phrase(&pending).or(Some(String::new()));
+                pending.clear();
+            }
+            Token::Special(separator @ (',' | ';')) => {
+                if !angle_read {
+                    push_mailbox(&mut addresses, &pending, &group);
+                }
+                pending.clear();
+                angle_read = false;
+                if *separator == ';' {
+                    group = None;
+                }
+            }
+            token => pending.push(token),
+        }
+    }
+    if !angle_read {
+        push_mailbox(&mut addresses, &pending, &group);
+    }
+    addresses
+}
+
+/// Add the address of a mailbox without angle brackets, `user@domain (Display Name)` for example.
+fn push_mailbox(addresses: &mut Vec<Address>, tokens: &[&Token], group: &Option<String>) {
+    if let Some(address) = addr_spec(tokens) {
+        let name = tokens.iter()
+            .find_map(|token| match token {
+                Token::Comment(comment) if !comment.trim().is_empty() => Some(decode(comment.trim())),
+                _ => None,
+            });
+        addresses.push(Address { name, address, group: group.clone() });
+    }
+}
+
+/// Display name from phrase tokens, words separated by a single space.
+fn phrase(tokens: &[&Token]) -> Option<String> {
+    let words: Vec<&str> = tokens.iter()
+        .filter_map(|token| match token {
+            Token::Word(word) | Token::Quoted(word) => Some(word.as_str()),
+            Token::Special('@') => Some("@"),
+            _ => None,
+        })
+        .collect();
+    let name = words.join(" ");
+    (!name.trim().is_empty()).then(|| decode(name.trim()))
+}
+
+/// Address from addr-spec tokens, spaces and comments removed.
+fn addr_spec(tokens: &[&Token]) -> Option<String> {
+    let address: String = tokens.iter()
+        .filter_map(|token| match token {
+            Token::Word(word) => Some(word.clone()),
+            Token::Quoted(word) => Some(quote(word)),
+            Token::Special('@') => Some("@".to_string()),
+            _ => None,
+        })
+        .collect();
+    (!address.is_empty()).then_some(address)
+}
+
+/// Quote a local part when it is not a valid dot-atom.
+fn quote(local_part: &str) -> String {
+    if !local_part.is_empty() && local_part.split('.').all(|atom| !atom.is_empty() && atom.chars().all(is_atext)) {
+        local_part.to_string()
+    } else {
+        format!("\"{}\"", local_part.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn decode(value: &str) -> String {
+    Decoder::new()
+        .too_long_encoded_word_strategy(RecoverStrategy::Skip)
+        .decode(value.as_bytes())
+        .unwrap_or_else(|_| value.to_string())
+}
+
+fn is_atext(c: char) -> bool {
+    !c.is_ascii() || c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+fn tokenize(value: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut quoted = String::new();
+                while let Some(c) = chars.next() && c != '"' {
+                    quoted.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+                }
+                tokens.push(Token::Quoted(quoted));
+            }
+            '(' => {
+                let mut comment = String::new();
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => comment.extend(chars.next()),
+                        '(' => { depth += 1; comment.push(c); }
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                            comment.push(c);
+                        }
+                        c => comment.push(c),
+                    }
+                }
+                tokens.push(Token::Comment(comment));
+            }
+            '[' => {
+                let mut literal = String::from('[');
+                for c in chars.by_ref() {
+                    literal.push(c);
+                    if c == ']' {
+                        break;
+                    }
+                }
+                tokens.push(Token::Word(literal));
+            }
+            '<' | '>' | '@' | ',' | ':' | ';' => tokens.push(Token::Special(c)),
+            c => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() && !c.is_whitespace() && !"\"()[<>@,:;".contains(c) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(name: Option<&str>, address: &str) -> Address {
+        Address { name: name.map(str::to_string), address: address.to_string(), group: None }
+    }
+
+    #[test]
+    fn test_parse_mailboxes() {
+        assert_eq!(vec![address(Some("Jean Dupont"), "jean@example.fr")], parse_address_list("\"Jean Dupont\" <jean@example.fr>"));
+        assert_eq!(vec![address(Some("Jean Dupont"), "jean@example.fr")], parse_address_list("Jean  Dupont <jean@example.fr>"));
+        assert_eq!(vec![address(None, "jean@example.fr")], parse_address_list("jean@example.fr"));
+        assert_eq!(vec![address(None, "jean@example.fr")], parse_address_list("<jean@example.fr>"));
+        assert_eq!(
+            vec![address(Some("Dupont, Jean"), "jean@example.fr"), address(None, "marie@example.fr")],
+            parse_address_list("\"Dupont, Jean\" <jean@example.fr>,\n marie@example.fr"));
+        assert_eq!(vec![address(Some("a \"b\""), "\"a b\"@example.fr")], parse_address_list("\"a \\\"b\\\"\" <\"a b\"@example.fr>"));
+    }
+
+    #[test]
+    fn test_parse_comments_and_obsolete_forms() {
+        assert_eq!(vec![address(Some("Jean Dupont"), "jean@example.fr")], parse_address_list("jean@example.fr (Jean Dupont)"));
+        assert_eq!(vec![address(Some("Jean"), "jean@example.fr")], parse_address_list("Jean (le (vrai)) <jean@example.fr> (bureau)"));
+        assert_eq!(vec![address(None, "jean.dupont@example.fr")], parse_address_list("jean . dupont @ example.fr"));
+        assert_eq!(vec![address(Some("Jean"), "jean@example.fr")], parse_address_list("Jean <@relay.example.org,@other.example.org:jean@example.fr>"));
+        assert_eq!(vec![address(None, "jean@[192.168.0.1]")], parse_address_list("jean@[192.168.0.1]"));
+        assert_eq!(vec![address(None, "a@b.c"), address(None, "d@e.f")], parse_address_list(",a@b.c,, d@e.f,"));
+    }
+
+    #[test]
+    fn test_parse_groups() {
+        let addresses = parse_address_list("Equipe: jean@example.fr, Marie <marie@example.fr>;, paul@example.fr");
+        assert_eq!(3, addresses.len());
+        assert_eq!(Some("Equipe".to_string()), addresses[0].group);
+        assert_eq!(Some("Marie".to_string()), addresses[1].name);
+        assert_eq!(Some("Equipe".to_string()), addresses[1].group);
+        assert_eq!(None, addresses[2].group);
+        assert!(parse_address_list("undisclosed-recipients:;").is_empty());
+    }
+
+    #[test]
+    fn test_parse_encoded_words() {
+        let addresses = parse_address_list("=?ISO-8859-1?Q?Andr=E9?= Pirard <PIRARD@vm1.ulg.ac.be>, =?UTF-8?B?w4lsw6hu?= =?UTF-8?Q?e?= <e@x.fr>");
+        assert_eq!(Some("André Pirard".to_string()), addresses[0].name);
+        assert_eq!("pirard@vm1.ulg.ac.be", addresses[0].normalized());
+        assert_eq!(Some("Élène".to_string()), addresses[1].name);
+    }
+}
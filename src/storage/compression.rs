@@ -0,0 +1,259 @@
+use std::{fs::{self, DirBuilder, File}, io::{self, BufReader, Read, Seek, SeekFrom}, path::{Path, PathBuf}, time::UNIX_EPOCH};
+
+use flate2::read::MultiGzDecoder;
+use tracing::{debug, instrument, warn};
+use xz2::read::XzDecoder;
+
+use crate::storage::{index::fnv1a, write_atomically, MailboxError};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const CACHE_DIR: &str = "mbox-viewer";
+/// Size of the head and tail of an archive covered by the checksums of its cache name.
+const CHECKSUM_SIZE: usize = 64 * 1024;
+
+/// Compression of a mailbox archive, detected from its first bytes whatever its file extension.
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+
+    pub fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(XZ_MAGIC) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Decompressing reader, concatenated streams being read as a single one.
+    fn decoder<'a>(&self, reader: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+
+}
+
+/// Path of a plain mailbox with the content of `file_path` : the file itself when it is not compressed,
+/// otherwise a cache file in the user cache directory, decompressed again when the archive changes.
+/// The cache is named after the archive path, size, modification time and head and tail checksums,
+/// and only trusted when it is a file of the current user.
+pub fn plain_path(file_path: &str) -> Result<String, MailboxError> {
+    plain_path_with(file_path, user_cache_home)
+}
+
+/// Path of a plain mailbox with the content of `file_path` as `plain_path`, caches being kept in
+/// `cache_home/mbox-viewer` rather than the user cache directory.
+pub fn plain_path_in(file_path: &str, cache_home: &Path) -> Result<String, MailboxError> {
+    plain_path_with(file_path, || Some(cache_home.to_path_buf()))
+}
+
+#[instrument(skip(cache_home))]
+fn plain_path_with(file_path: &str, cache_home: impl FnOnce() -> Option<PathBuf>) -> Result<String, MailboxError> {
+    let mut magic = [0u8; XZ_MAGIC.len()];
+    let mut file = File::open(file_path)?;
+    let magic_len = file.read(&mut magic)?;
+    let Some(compression) = Compression::detect(&magic[..magic_len]) else {
+        return Ok(file_path.to_string());
+    };
+
+    let metadata = file.metadata()?;
+    let mtime = metadata.modified().ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let (head_checksum, tail_checksum) = checksums(&mut file, metadata.len())?;
+    let canonical = fs::canonicalize(file_path)?;
+    let prefix = format!("{:016x}-", fnv1a(canonical.to_string_lossy().as_bytes()));
+    let cache_dir = cache_dir(&cache_home().ok_or(MailboxError::MboxDecompressError)?)?;
+    let cache_path = cache_dir.join(format!("{prefix}{}-{}.{}-{head_checksum:016x}{tail_checksum:016x}.mbox",
+        metadata.len(), mtime.as_secs(), mtime.subsec_nanos()));
+    if !is_private(&cache_path) {
+        debug!("Decompress {compression} archive to {}", cache_path.display());
+        decompress(compression, File::open(file_path)?, &cache_path)?;
+        remove_stale_caches(&cache_dir, &prefix, &cache_path);
+    }
+    Ok(cache_path.to_string_lossy().to_string())
+}
+
+/// User cache directory, `$XDG_CACHE_HOME` or `~/.cache`.
+fn user_cache_home() -> Option<PathBuf> {
+    std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from).filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+}
+
+/// Directory of the caches in `cache_home`, created readable by the current user only and refused
+/// when another user could write in it.
+fn cache_dir(cache_home: &Path) -> Result<PathBuf, MailboxError> {
+    fs::create_dir_all(cache_home)?;
+    let cache_dir = cache_home.join(CACHE_DIR);
+    let mut builder = DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    if let Err(e) = builder.create(&cache_dir) && e.kind() != io::ErrorKind::AlreadyExists {
+        return Err(e.into());
+    }
+    if !is_private(&cache_dir) {
+        warn!("Cache directory {} is a link or writable by other users", cache_dir.display());
+        return Err(MailboxError::MboxDecompressError);
+    }
+    Ok(cache_dir)
+}
+
+/// Whether `path` is a file or a directory of the current user that other users cannot write, not a link.
+fn is_private(path: &Path) -> bool {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // SAFETY: geteuid has no preconditions and cannot fail
+        if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o022 != 0 {
+            return false;
+        }
+    }
+    metadata.is_file() || metadata.is_dir()
+}
+
+/// FNV-1a checksums of the head and the tail of a file of `len` bytes.
+fn checksums(file: &mut File, len: u64) -> io::Result<(u64, u64)> {
+    let mut buf = vec![0; CHECKSUM_SIZE.min(len as usize)];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut buf)?;
+    let head = fnv1a(&buf);
+    file.seek(SeekFrom::Start(len - buf.len() as u64))?;
+    file.read_exact(&mut buf)?;
+    Ok((head, fnv1a(&buf)))
+}
+
+/// Decompress the archive to `cache_path`, written atomically so that a partial cache is never used.
+fn decompress(compression: Compression, file: File, cache_path: &Path) -> Result<(), MailboxError> {
+    write_atomically(cache_path, true, MailboxError::MboxDecompressError, |cache| {
+        compression.decoder(BufReader::new(file))
+            .and_then(|mut decoder| io::copy(&mut decoder, cache))
+            .map(drop)
+            .or(Err(MailboxError::MboxDecompressError))
+    })
+}
+
+/// Remove the caches of previous versions of the same archive, and their index sidecars.
+fn remove_stale_caches(cache_dir: &Path, prefix: &str, cache_path: &Path) {
+    let current = cache_path.file_name().unwrap_or_default().to_string_lossy();
+    let Ok(entries) = fs::read_dir(cache_dir) else { return };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with(prefix) && !name.starts_with(&*current) && fs::remove_file(entry.path()).is_err() {
+            warn!("Unable to remove stale cache {name}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    use flate2::{write::GzEncoder, Compression as GzLevel};
+    use xz2::write::XzEncoder;
+
+    use crate::storage::tmp_dir;
+
+    use super::*;
+
+    fn compress(compression: Compression, content: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(vec![], GzLevel::default());
+                encoder.write_all(content).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(content, 0).unwrap(),
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(vec![], 6);
+                encoder.write_all(content).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            assert_eq!(Some(compression), Compression::detect(&compress(compression, b"From a\n")));
+        }
+        assert_eq!(None, Compression::detect(b"From a\n"));
+        assert_eq!(None, Compression::detect(b""));
+    }
+
+    #[test]
+    fn test_plain_path() {
+        let dir = tmp_dir(&[]);
+        let cache_home = dir.path().join("cache");
+        let content = fs::read("datasets/test_lex.mbox").unwrap();
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let path = dir.path().join(format!("compressed-{compression}.mbox")).to_string_lossy().to_string();
+            fs::write(&path, compress(compression, &content)).unwrap();
+            let cache_path = plain_path_in(&path, &cache_home).unwrap();
+            assert!(cache_path.starts_with(&*cache_home.join(CACHE_DIR).to_string_lossy()));
+            assert_eq!(content, fs::read(&cache_path).unwrap());
+            assert_eq!(cache_path, plain_path_in(&path, &cache_home).unwrap());
+
+            // a new version of the archive replaces the previous cache
+            let mut concatenated = compress(compression, &content);
+            concatenated.extend(compress(compression, b"From b\n"));
+            fs::write(&path, concatenated).unwrap();
+            let new_cache_path = plain_path_in(&path, &cache_home).unwrap();
+            assert!(fs::read(&new_cache_path).unwrap().ends_with(b"\nFrom b\n"));
+            assert_ne!(cache_path, new_cache_path);
+            assert!(!Path::new(&cache_path).exists());
+        }
+        assert_eq!("datasets/test_lex.mbox", plain_path_in("datasets/test_lex.mbox", &cache_home).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_plain_path_untrusted_cache() {
+        let dir = tmp_dir(&[]);
+        let cache_home = dir.path().join("cache");
+        let content = fs::read("datasets/test_lex.mbox").unwrap();
+        let path = dir.path().join("untrusted.mbox.gz").to_string_lossy().to_string();
+        fs::write(&path, compress(Compression::Gzip, &content)).unwrap();
+        let cache_path = plain_path_in(&path, &cache_home).unwrap();
+        assert!(fs::metadata(cache_home.join(CACHE_DIR)).unwrap().permissions().mode() & 0o077 == 0);
+
+        // a link planted at the cache path is replaced by a decompressed cache
+        let planted = dir.path().join("planted.mbox");
+        fs::write(&planted, b"From planted\n\nbody\n").unwrap();
+        fs::remove_file(&cache_path).unwrap();
+        std::os::unix::fs::symlink(&planted, &cache_path).unwrap();
+        assert_eq!(cache_path, plain_path_in(&path, &cache_home).unwrap());
+        assert!(!fs::symlink_metadata(&cache_path).unwrap().file_type().is_symlink());
+        assert_eq!(content, fs::read(&cache_path).unwrap());
+        assert_eq!(b"From planted\n\nbody\n", fs::read(&planted).unwrap().as_slice());
+
+        // a cache directory writable by other users is refused
+        fs::set_permissions(cache_home.join(CACHE_DIR), fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(plain_path_in(&path, &cache_home).is_err_and(|e| e == MailboxError::MboxDecompressError));
+    }
+
+    #[test]
+    fn test_plain_path_corrupted() {
+        let dir = tmp_dir(&[]);
+        let path = dir.path().join("corrupted.mbox.gz").to_string_lossy().to_string();
+        fs::write(&path, [0x1f, 0x8b, 0x08, 0x00, 0x01, 0x02]).unwrap();
+        assert!(plain_path_in(&path, &dir.path().join("cache")).is_err_and(|e| e == MailboxError::MboxDecompressError));
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+/// Zone names still found in `Date` headers, with their offset in minutes. Ambiguous names like `IST`
+/// are left out and, as military zones, read as UTC.
+const ZONES: [(&str, i32); 27] = [
+    ("ut", 0), ("utc", 0), ("gmt", 0), ("z", 0), ("wet", 0),
+    ("est", -300), ("edt", -240), ("cst", -360), ("cdt", -300), ("mst", -420), ("mdt", -360), ("pst", -480), ("pdt", -420),
+    ("akst", -540), ("akdt", -480), ("hst", -600),
+    ("bst", 60), ("west", 60), ("cet", 60), ("met", 60), ("cest", 120), ("mest", 120), ("eet", 120), ("eest", 180), ("msk", 180),
+    ("jst", 540), ("aest", 600),
+];
+
+/// Parse a `Date` header value, keeping its offset. Beyond RFC 5322 dates, accepts the obsolete and
+/// broken forms found in real mailboxes : two or three digit years, named zones, missing or misplaced
+/// commas, comments like `(UTC)`, asctime order (`Mon Aug  4 11:56:07 2025`) and ISO-8601 values.
+/// A missing or unknown zone is read as UTC.
+pub fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = strip_comments(value);
+    let value = value.trim();
+    DateTime::parse_from_rfc2822(value).ok()
+        .or_else(|| parse_iso8601(value))
+        .or_else(|| parse_tokens(value))
+}
+
+fn strip_comments(value: &str) -> String {
+    let mut res = String::with_capacity(value.len());
+    let mut depth = 0;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => res.push(c),
+            _ => {}
+        }
+    }
+    res
+}
+
+/// `2025-08-04T11:56:07+02:00`, with a space instead of `T`, fractional seconds or no zone.
+fn parse_iso8601(value: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime);
+    }
+    let value = value.replacen(' ', "T", 1).replace(' ', "");
+    ["%Y-%m-%dT%H:%M:%S%.f%:z", "%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%dT%H:%M%:z"].iter()
+        .find_map(|format| DateTime::parse_from_str(&value, format).ok())
+        .or_else(|| {
+            let value = value.trim_end_matches('Z');
+            ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"].iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+                .map(|datetime| datetime.and_utc().fixed_offset())
+        })
+}
+
+/// Date from its tokens in any order : month name, day then year numbers, `hh:mm[:ss]` time and zone.
+fn parse_tokens(value: &str) -> Option<DateTime<FixedOffset>> {
+    let mut month = None;
+    let mut numbers = vec![];
+    let mut time = None;
+    let mut offset = None;
+    for token in value.split([' ', '\t', ',']).filter(|token| !token.is_empty()) {
+        let lower = token.trim_end_matches('.').to_lowercase();
+        if token.contains(':') && time.is_none() && token.starts_with(|c: char| c.is_ascii_digit()) {
+            let (time_token, zone) = split_glued_zone(token);
+            time = Some(parse_time(time_token)?);
+            if let Some(zone) = zone {
+                offset = Some(parse_offset(zone)?);
+            }
+        } else if token.starts_with(['+', '-']) {
+            offset = Some(parse_offset(token)?);
+        } else if token.chars().all(|c| c.is_ascii_digit()) {
+            numbers.push(token);
+        } else if let Some(idx) = MONTHS.iter().position(|name| lower.starts_with(name)) && month.is_none() && lower.len() >= 3 {
+            month = Some(idx as u32 + 1);
+        } else if WEEKDAYS.iter().any(|name| lower.starts_with(name)) && lower.len() >= 3 {
+            continue;
+        } else if lower.chars().all(|c| c.is_ascii_alphabetic()) {
+            // unknown and military zones carry no reliable offset
+            offset = offset.or(Some(ZONES.iter().find(|(name, _)| *name == lower).map_or(0, |(_, minutes)| *minutes)));
+        } else {
+            return None;
+        }
+    }
+    let [day, year] = numbers.as_slice() else {
+        return None;
+    };
+    let date = NaiveDate::from_ymd_opt(parse_year(year)?, month?, day.parse().ok()?)?;
+    let offset = FixedOffset::east_opt(offset.unwrap_or(0) * 60)?;
+    date.and_time(time?).and_local_timezone(offset).single()
+}
+
+/// Split a zone glued to the time, `11:56:07+0200` or `11:56:07Z`.
+fn split_glued_zone(token: &str) -> (&str, Option<&str>) {
+    match token.find(|c: char| c == '+' || c == '-' || c.is_ascii_alphabetic()) {
+        Some(idx) => (&token[..idx], Some(&token[idx..])),
+        None => (token, None),
+    }
+}
+
+/// `hh:mm` or `hh:mm:ss`, fractional seconds being dropped and a leap second read as the previous one.
+fn parse_time(token: &str) -> Option<NaiveTime> {
+    let mut parts = token.split(':');
+    let hour = parts.next()?.parse().ok()?;
+    let minute = parts.next()?.parse().ok()?;
+    let second: u32 = match parts.next() {
+        Some(second) => second.split('.').next()?.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    NaiveTime::from_hms_opt(hour, minute, second.min(59))
+}
+
+/// Offset in minutes of `+hhmm`, `+hh:mm`, `+hh` or a zone name.
+fn parse_offset(token: &str) -> Option<i32> {
+    let lower = token.to_lowercase();
+    if let Some((_, minutes)) = ZONES.iter().find(|(name, _)| *name == lower) {
+        return Some(*minutes);
+    }
+    let (sign, digits) = match token.split_at_checked(1)? {
+        ("+", digits) => (1, digits.replace(':', "")),
+        ("-", digits) => (-1, digits.replace(':', "")),
+        _ => return None,
+    };
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i32>().ok()?, 0),
+        4 => (digits[..2].parse::<i32>().ok()?, digits[2..].parse::<i32>().ok()?),
+        _ => return None,
+    };
+    (minutes < 60).then_some(sign * (hours * 60 + minutes))
+}
+
+/// Year of RFC 5322 obsolete syntax : two digit years below 50 are in the 2000s, three digit years
+/// are counted from 1900.
+fn parse_year(token: &str) -> Option<i32> {
+    let year: i32 = token.parse().ok()?;
+    match token.len() {
+        1..=2 if year < 50 => Some(2000 + year),
+        1..=3 => Some(1900 + year),
+        4 => Some(year),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date_corpus() {
+        let corpus = std::fs::read_to_string("datasets/test_dates.tsv").unwrap();
+        for line in corpus.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let (value, expected) = line.split_once('\t').unwrap();
+            let expected = Some(expected).filter(|expected| !expected.is_empty())
+                .map(|expected| DateTime::parse_from_rfc3339(expected).unwrap());
+            let parsed = parse_date(value);
+            assert_eq!(expected, parsed, "{value}");
+            assert_eq!(expected.map(|expected| expected.offset().local_minus_utc()), parsed.map(|parsed| parsed.offset().local_minus_utc()), "{value}");
+        }
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(Some(120), parse_offset("+0200"));
+        assert_eq!(Some(-330), parse_offset("-05:30"));
+        assert_eq!(Some(-420), parse_offset("PDT"));
+        assert_eq!(None, parse_offset("+2"));
+        assert_eq!(None, parse_offset("+0290"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{borrow::Cow, collections::BTreeMap, fs, path::{Path, PathBuf}, time::SystemTime};
+
+use tracing::{debug, instrument, warn};
+
+use crate::{mailbox::Attachment, storage::{message::{EmailFilePtr, MessageFile}, view::EmailView, MailboxError}, Email, MailStorageRepository};
+
+const EML_EXTENSION: &str = "eml";
+
+#[derive(Debug)]
+struct EmlMessage {
+    path: PathBuf,
+    /// Modification time of the file when it was parsed.
+    modified: Option<SystemTime>,
+    email: EmailFilePtr,
+}
+
+/// Directory of `.eml` files, one message per file, scanned recursively. Emails are identified by the
+/// path of their file relative to the directory, with `/` separators.
+#[derive(Debug)]
+pub struct EmlDirectory {
+    messages: BTreeMap<String, EmlMessage>,
+}
+
+impl EmlDirectory {
+
+    #[instrument]
+    pub fn new(path: &str) -> Result<Self, MailboxError> {
+        let root = PathBuf::from(path);
+        if !root.is_dir() {
+            return Err(MailboxError::EmlDirectoryNotFound);
+        }
+        let mut messages = BTreeMap::new();
+        let mut directories = vec![root.clone()];
+        while let Some(directory) = directories.pop() {
+            for entry in fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                if path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.')) {
+                    continue;
+                }
+                // links are not followed, they could loop or lead out of the directory
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    directories.push(path);
+                } else if file_type.is_file() && path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case(EML_EXTENSION)) {
+                    let id = relative_id(&root, &path);
+                    let modified = entry.metadata()?.modified().ok();
+                    let data = fs::read(&path)?;
+                    match EmailFilePtr::parse(&data, 0..data.len()) {
+                        Ok(email) => {
+                            messages.insert(id, EmlMessage { path, modified, email });
+                        }
+                        Err(_) => warn!("Skip invalid eml file {id}"),
+                    }
+                }
+            }
+        }
+        debug!("Loaded {} emails from eml directory", messages.len());
+        Ok(EmlDirectory { messages })
+    }
+
+    /// Message `id` with the content of its file, failing when the file was changed since it was parsed.
+    fn read(&self, id: &str) -> Result<MessageFile<'_>, MailboxError> {
+        let message = self.messages.get(id).ok_or(MailboxError::EmailNotFound)?;
+        MessageFile::read(&message.path, &message.email, message.modified)
+    }
+
+}
+
+/// Path of `path` relative to `root`, with `/` separators on all platforms.
+fn relative_id(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl MailStorageRepository for EmlDirectory {
+    type EmailId = String;
+
+    fn get_email(&self, id: &Self::EmailId) -> Result<Email<Self::EmailId>, MailboxError> {
+        self.read(id)?.email(id.clone())
+    }
+
+    fn count_emails(&self) -> Result<usize, MailboxError> {
+        Ok(self.messages.len())
+    }
+
+    fn emails(&self) -> impl Iterator<Item = Email<Self::EmailId>> {
+        self.messages.keys().filter_map(|id| self.get_email(id).ok())
+    }
+
+    fn email_view(&self, id: &Self::EmailId) -> Result<EmailView<'_, Self::EmailId>, MailboxError> {
+        Ok(self.read(id)?.view(id.clone()))
+    }
+
+    fn email_views(&self) -> impl Iterator<Item = EmailView<'_, Self::EmailId>> {
+        self.messages.keys().filter_map(|id| self.email_view(id).ok())
+    }
+
+    fn header(&self, id: &Self::EmailId, name: &str) -> Result<Option<String>, MailboxError> {
+        self.read(id)?.header(name)
+    }
+
+    fn raw_email(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        Ok(self.read(id)?.raw_email())
+    }
+
+    fn raw_headers(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        Ok(self.read(id)?.raw_headers())
+    }
+
+    fn raw_part(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        self.read(id)?.raw_part(index)
+    }
+
+    fn attachments(&self, id: &Self::EmailId) -> Result<Vec<Attachment>, MailboxError> {
+        Ok(self.read(id)?.attachments())
+    }
+
+    fn attachment_content(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        self.read(id)?.attachment_content(index)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::tmp_dir;
+
+    use super::*;
+
+    const MESSAGE: &str = "From: Jean <jean@example.fr>\r\nDate: Mon, 4 Aug 2025 11:56:07 +0800\r\nSubject: Bonjour\r\n\r\nCorps\r\n";
+
+    #[test]
+    fn test_read_eml_directory() {
+        let dir = tmp_dir(&[
+            ("a.eml", MESSAGE),
+            ("projet/2025/b.EML", &MESSAGE.replace("Bonjour", "Projet")),
+            ("projet/notes.txt", MESSAGE),
+            ("projet/invalid.eml", "Subject: no date\r\n\r\n"),
+            (".hidden/c.eml", MESSAGE),
+        ]);
+        let path = dir.path();
+
+        let directory = EmlDirectory::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(2, directory.count_emails().unwrap());
+        assert_eq!(vec!["a.eml", "projet/2025/b.EML"], directory.emails().map(|email| email.id).collect::<Vec<_>>());
+        let email = directory.get_email(&"projet/2025/b.EML".to_string()).unwrap();
+        assert_eq!(Some("Projet"), email.subject.as_deref());
+        assert_eq!(Some("Corps\r\n".to_string()), email.body_text);
+        assert!(directory.get_email(&"projet/invalid.eml".to_string()).is_err_and(|e| e == MailboxError::EmailNotFound));
+        let id = "a.eml".to_string();
+        assert_eq!(MESSAGE.as_bytes(), directory.raw_email(&id).unwrap().as_ref());
+        assert_eq!(b"From: Jean <jean@example.fr>\r\nDate: Mon, 4 Aug 2025 11:56:07 +0800\r\nSubject: Bonjour\r\n".as_slice(), directory.raw_headers(&id).unwrap().as_ref());
+        assert_eq!(MESSAGE.as_bytes(), directory.raw_part(&id, 0).unwrap().as_ref());
+        assert!(directory.raw_part(&id, 1).is_err());
+
+        // a file truncated or edited after the directory was read
+        fs::write(path.join("a.eml"), &MESSAGE[..20]).unwrap();
+        assert!(directory.get_email(&id).is_err_and(|e| e == MailboxError::EmailFileChanged));
+        assert!(directory.raw_email(&id).is_err_and(|e| e == MailboxError::EmailFileChanged));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symbolic_links_not_followed() {
+        let dir = tmp_dir(&[("a.eml", MESSAGE)]);
+        let path = dir.path();
+        let outside = tmp_dir(&[("b.eml", MESSAGE)]);
+        std::os::unix::fs::symlink("..", path.join("loop")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), path.join("outside")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("b.eml"), path.join("b.eml")).unwrap();
+
+        let directory = EmlDirectory::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(vec!["a.eml"], directory.emails().map(|email| email.id).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_not_a_directory() {
+        assert!(EmlDirectory::new("datasets/test_lex.mbox").is_err_and(|e| e == MailboxError::EmlDirectoryNotFound));
+    }
+}
@@ -0,0 +1,247 @@
+use std::borrow::Cow;
+
+use base64::{alphabet, engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig}, Engine};
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use quoted_printable::ParseMode;
+
+use crate::storage::{header::next_line, MailboxError};
+
+const BASE64: GeneralPurpose = GeneralPurpose::new(&alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true));
+
+/// Content-Transfer-Encoding of a MIME part (RFC 2045 section 6).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransferEncoding {
+    SevenBit,
+    EightBit,
+    Binary,
+    QuotedPrintable,
+    Base64,
+    Uuencode,
+}
+
+impl TransferEncoding {
+
+    /// Encoding declared in a `Content-Transfer-Encoding` value, case-insensitive.
+    pub fn parse(declared: &str) -> Result<Self, MailboxError> {
+        match declared.trim().to_ascii_lowercase().as_str() {
+            "" | "7bit" => Ok(TransferEncoding::SevenBit),
+            "8bit" => Ok(TransferEncoding::EightBit),
+            "binary" => Ok(TransferEncoding::Binary),
+            "quoted-printable" => Ok(TransferEncoding::QuotedPrintable),
+            "base64" => Ok(TransferEncoding::Base64),
+            "uuencode" | "x-uuencode" | "x-uue" => Ok(TransferEncoding::Uuencode),
+            _ => Err(MailboxError::UnknownTransferEncodingError),
+        }
+    }
+
+    /// Declared encoding, replaced by uuencode when an unencoded content is an inline uuencoded block.
+    pub fn detect(declared: &str, content: &[u8]) -> Result<Self, MailboxError> {
+        match Self::parse(declared)? {
+            TransferEncoding::SevenBit | TransferEncoding::EightBit if is_uuencoded(content) =>
+                Ok(TransferEncoding::Uuencode),
+            encoding => Ok(encoding),
+        }
+    }
+
+    pub fn decode<'a>(&self, content: &'a [u8]) -> Result<Cow<'a, [u8]>, MailboxError> {
+        match self {
+            TransferEncoding::SevenBit | TransferEncoding::EightBit | TransferEncoding::Binary =>
+                Ok(Cow::Borrowed(content)),
+            TransferEncoding::QuotedPrintable => quoted_printable::decode(content, ParseMode::Robust)
+                .map(Cow::Owned)
+                .or(Err(MailboxError::DecodeQuotedPrintableError)),
+            TransferEncoding::Base64 => {
+                let encoded: Vec<u8> = content.iter().copied()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == b'+' || *c == b'/')
+                    .collect();
+                BASE64.decode(encoded)
+                    .map(Cow::Owned)
+                    .or(Err(MailboxError::DecodeBase64Error))
+            },
+            TransferEncoding::Uuencode => uudecode(content).map(Cow::Owned),
+        }
+    }
+
+    /// Size of the decoded content, computed without decoding. Exact for base64 and unencoded
+    /// contents, an upper bound for quoted-printable and uuencode.
+    pub fn decoded_len(&self, content: &[u8]) -> usize {
+        match self {
+            TransferEncoding::Base64 => content.iter()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == b'+' || **c == b'/')
+                .count() * 3 / 4,
+            _ => content.len(),
+        }
+    }
+
+}
+
+/// Transcode `content` from `charset` to UTF-8. The returned flag is set when the text had to be
+/// repaired : unknown charset, invalid sequences replaced, or undeclared charset which is not UTF-8.
+pub fn decode_charset<'a>(content: &'a [u8], charset: Option<&str>) -> (Cow<'a, str>, bool) {
+    match charset.map(|label| Encoding::for_label(label.trim().as_bytes())) {
+        Some(Some(encoding)) => {
+            let (text, had_errors) = encoding.decode_without_bom_handling(content);
+            (text, had_errors)
+        },
+        Some(None) => {
+            let (text, _) = UTF_8.decode_without_bom_handling(content);
+            (text, true)
+        },
+        None => match std::str::from_utf8(content) {
+            Ok(text) => (Cow::Borrowed(text), false),
+            Err(_) => (WINDOWS_1252.decode_without_bom_handling(content).0, true),
+        },
+    }
+}
+
+/// Whether the first non blank line of `content` is an uuencode `begin <mode> <name>` line.
+fn is_uuencoded(content: &[u8]) -> bool {
+    let mut pos = 0;
+    while pos < content.len() {
+        let (line, next) = next_line(content, pos);
+        let line = &content[line];
+        if !line.iter().all(u8::is_ascii_whitespace) {
+            return parse_begin_line(line).is_some();
+        }
+        pos = next;
+    }
+    false
+}
+
+/// File name of an uuencode `begin <mode> <name>` line.
+fn parse_begin_line(line: &[u8]) -> Option<&[u8]> {
+    let rest = line.strip_prefix(b"begin ")?;
+    let mode_len = rest.iter().position(|&c| c == b' ')?;
+    if mode_len == 0 || !rest[..mode_len].iter().all(|c| (b'0'..=b'7').contains(c)) {
+        return None;
+    }
+    Some(&rest[mode_len + 1..])
+}
+
+fn uudecode(content: &[u8]) -> Result<Vec<u8>, MailboxError> {
+    let mut decoded = vec![];
+    let mut pos = 0;
+    let mut started = false;
+
+    while pos < content.len() {
+        let (line, next) = next_line(content, pos);
+        let line = &content[line];
+        pos = next;
+        if !started {
+            started = parse_begin_line(line).is_some();
+            continue;
+        }
+        if line == b"end" {
+            return Ok(decoded);
+        }
+        let Some((&len_char, encoded)) = line.split_first() else { continue };
+        let len = (len_char.wrapping_sub(b' ') & 0x3f) as usize;
+        if len == 0 {
+            continue;
+        }
+        if encoded.len() < (len * 4).div_ceil(3) {
+            return Err(MailboxError::DecodeUuencodeError);
+        }
+        let mut line_bytes = Vec::with_capacity(len + 2);
+        for group in encoded.chunks(4) {
+            let mut sextets = [0u8; 4];
+            for (idx, c) in group.iter().enumerate() {
+                sextets[idx] = c.wrapping_sub(b' ') & 0x3f;
+            }
+            line_bytes.push(sextets[0] << 2 | sextets[1] >> 4);
+            line_bytes.push(sextets[1] << 4 | sextets[2] >> 2);
+            line_bytes.push(sextets[2] << 6 | sextets[3]);
+        }
+        line_bytes.truncate(len);
+        decoded.extend_from_slice(&line_bytes);
+    }
+    if started {
+        Ok(decoded)
+    } else {
+        Err(MailboxError::DecodeUuencodeError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_declared_encoding() {
+        assert_eq!(TransferEncoding::Base64, TransferEncoding::parse(" BASE64 ").unwrap());
+        assert_eq!(TransferEncoding::QuotedPrintable, TransferEncoding::parse("Quoted-Printable").unwrap());
+        assert_eq!(TransferEncoding::SevenBit, TransferEncoding::parse("").unwrap());
+        assert_eq!(Err(MailboxError::UnknownTransferEncodingError), TransferEncoding::parse("x-gzip"));
+    }
+
+    #[test]
+    fn test_decode_base64_multiline() {
+        let decoded = TransferEncoding::Base64.decode(b"Qm9uam91ciDDoCB0\r\nb3Vz\n").unwrap();
+        assert_eq!("Bonjour à tous".as_bytes(), decoded.as_ref());
+    }
+
+    #[test]
+    fn test_base64_decoded_len() {
+        let content = b"Qm9uam91ciDDoCB0\r\nb3Vz\n";
+        assert_eq!(TransferEncoding::Base64.decode(content).unwrap().len(), TransferEncoding::Base64.decoded_len(content));
+        assert_eq!(4, TransferEncoding::Base64.decoded_len(b"JVBERg=="));
+    }
+
+    #[test]
+    fn test_decode_8bit_is_untouched() {
+        let content = "a = b =C3=A9".as_bytes();
+        let decoded = TransferEncoding::EightBit.decode(content).unwrap();
+        assert!(matches!(decoded, Cow::Borrowed(_)));
+        assert_eq!(content, decoded.as_ref());
+    }
+
+    #[test]
+    fn test_decode_quoted_printable() {
+        let decoded = TransferEncoding::QuotedPrintable.decode(b"caf=C3=A9 soft=\nbreak").unwrap();
+        assert_eq!("café softbreak".as_bytes(), decoded.as_ref());
+    }
+
+    #[test]
+    fn test_decode_charset_latin() {
+        let (text, repaired) = decode_charset(b"\xc9duConnect \xe9l\xe8ves", Some("ISO-8859-1"));
+        assert_eq!("ÉduConnect élèves", text);
+        assert!(!repaired);
+        let (text, repaired) = decode_charset(b"\xa4uro", Some("iso-8859-15"));
+        assert_eq!("€uro", text);
+        assert!(!repaired);
+    }
+
+    #[test]
+    fn test_decode_charset_shift_jis() {
+        let (text, repaired) = decode_charset(b"\x93\xfa\x96\x7b", Some("Shift_JIS"));
+        assert_eq!("日本", text);
+        assert!(!repaired);
+    }
+
+    #[test]
+    fn test_decode_charset_fallbacks() {
+        let (text, repaired) = decode_charset("déjà".as_bytes(), None);
+        assert!(matches!(text, Cow::Borrowed("déjà")));
+        assert!(!repaired);
+        let (text, repaired) = decode_charset(b"d\xe9j\xe0", None);
+        assert_eq!("déjà", text);
+        assert!(repaired);
+        let (text, repaired) = decode_charset(b"d\xe9j\xe0", Some("x-unknown"));
+        assert_eq!("d\u{FFFD}j\u{FFFD}", text);
+        assert!(repaired);
+        let (_, repaired) = decode_charset(b"d\xe9j\xe0", Some("utf-8"));
+        assert!(repaired);
+    }
+
+    #[test]
+    fn test_detect_and_decode_uuencode() {
+        let content = b"\nbegin 644 cat.txt\n#0V%T\n`\nend\n";
+        let encoding = TransferEncoding::detect("7bit", content).unwrap();
+        assert_eq!(TransferEncoding::Uuencode, encoding);
+        assert_eq!(b"Cat", encoding.decode(content).unwrap().as_ref());
+        assert_eq!(TransferEncoding::SevenBit, TransferEncoding::detect("7bit", b"Hello\nbegin 644 a\n").unwrap());
+    }
+}
@@ -1,45 +1,40 @@
-use std::{fs::File, io::{BufRead, BufReader, Error}, ops::Range, time::{Duration, Instant}};
+use std::{borrow::Cow, fs::File, io::Error, ops::Range, thread, time::{Duration, Instant}};
 
-use chrono::{DateTime, Utc};
 use memmap2::Mmap;
-use quoted_printable::{decode, ParseMode};
-use rfc2047_decoder::{Decoder, RecoverStrategy};
-use serde::Serialize;
-use tracing::{debug, error, instrument, warn};
+use tracing::{debug, instrument, warn};
 
-use crate::{storage::MailboxError, Email, MailStorageRepository};
+use crate::{mailbox::Attachment, storage::{compression, format::MboxFormat, index::{self, IndexValidation, MboxIndex}, lexer::{LexStats, MboxLexer}, message::{EmailFilePtr, MessageReader, ParseMode}, report::{ParseIssue, ParseReport}, view::EmailView, MailboxError}, Email, MailStorageRepository};
 
+pub use crate::storage::message::SeekRange;
 
-pub type SeekRange = (u64, u64);
+/// Minimum size of the chunks of a mailbox lexed in parallel.
+const PARALLEL_CHUNK_MIN_SIZE: usize = 16 * 1024 * 1024;
+
+/// Outcome of `MboxFile::refresh`.
+#[derive(Debug, PartialEq)]
+pub enum Refresh {
+    /// Ids of the appended messages, and of the last known message when it grew. Other ids are unchanged.
+    Appended(Vec<usize>),
+    /// The file was truncated or rewritten, or its last known message is no longer valid : ids may now
+    /// point to other messages.
+    Rewritten,
+}
 
 #[derive(Debug)]
 pub struct MboxFile {
+    file_path: String,
+    /// Plain file mapped in memory : `file_path` itself or its decompressed cache.
+    mmap_path: String,
     emails: Vec<EmailFilePtr>,
     file_mmap: Mmap,
-}
-
-#[derive(Serialize, Debug)]
-struct BodyFilePtr {
-    content_type: String,
-    content_transfer_encoding: String,
-    content: Range<usize>,
-}
-
-impl BodyFilePtr {
-
-    fn is_html(&self) -> bool {
-        self.content_type.contains("text/html")
-    }
-
-}
-
-#[derive(Debug)]
-struct EmailFilePtr {
-    email: Range<usize>,
-    subject: Range<usize>,
-    from: Range<usize>,
-    datetime: DateTime<Utc>,
-    bodies: Vec<BodyFilePtr>,
+    format: MboxFormat,
+    /// Whether `format` was detected from the content, to detect it again when the file is rewritten.
+    detected: bool,
+    mode: ParseMode,
+    lex_stats: LexStats,
+    report: ParseReport,
+    /// Size and checksums of the mapped file, to tell appended bytes from a rewrite on refresh.
+    validation: IndexValidation,
 }
 
 impl From<Error> for MailboxError {
@@ -49,216 +44,173 @@ impl From<Error> for MailboxError {
     }
 }
 
-enum Token {
-    StartEmail(u64),
-    Subject(u64),
-    Date(String),
-    From(u64),
-    Boby(u64),
-    ContentType(String),
-    ContentTransferEncoding(String),
-    End(u64),
-    Continuation,
-    Ignore
-}
+impl MboxFile {
 
-#[derive(Serialize)]
-struct EmailFilePtrValidator {
-    email: Option<SeekRange>,
-    subject: Option<SeekRange>,
-    from: Option<SeekRange>,
-    datetime: Option<DateTime<Utc>>,
-    bodies: Vec<BodyFilePtr>,
-}
+    /// Open a mailbox, its format variant being detected from its content.
+    pub fn new(file_path: &str) -> Result<Self, MailboxError> {
+        Self::open(file_path, None, ParseMode::Strict)
+    }
 
-impl EmailFilePtrValidator {
-    fn new() -> Self {
-        Self { email: None, subject: None, from: None, datetime: None, bodies: vec![] }
-    }
-    
-    fn validate(self) -> Result<EmailFilePtr, MailboxError> {
-        if self.email.is_some() && self.subject.is_some() && self.from.is_some() && self.datetime.is_some() {
-            Ok(EmailFilePtr{
-                email: Range { start: self.email.unwrap().0 as usize, end: self.email.unwrap().1 as usize },
-                subject: Range { start: self.subject.unwrap().0 as usize, end: self.subject.unwrap().1 as usize },
-                from: Range { start: self.from.unwrap().0 as usize, end: self.from.unwrap().1 as usize },
-                datetime: self.datetime.unwrap(),
-                bodies: self.bodies
-            })
-        } else {
-            if let Ok(value) = serde_json::to_string(&self) {
-                warn!("EmailFilePtr validation failed  : {value}");
-            } else {
-                warn!("EmailFilePtr validation failed.");
-            }
-            Err(MailboxError::MboxValidationError)
-        }
+    pub fn with_format(file_path: &str, format: MboxFormat) -> Result<Self, MailboxError> {
+        Self::open(file_path, Some(format), ParseMode::Strict)
     }
-}
 
-impl MboxFile {
+    /// Open a mailbox in the format variant `format`, detected when `None`, keeping messages with
+    /// missing headers in lenient mode.
+    pub fn with_mode(file_path: &str, format: Option<MboxFormat>, mode: ParseMode) -> Result<Self, MailboxError> {
+        Self::open(file_path, format, mode)
+    }
 
-    pub fn new(file_path: &str) -> Result<Self, MailboxError> {
-        let tokens = Self::lex(file_path)?;
+    /// Open the mailbox from its index sidecar when it is up to date, otherwise parse it and write the index.
+    /// Compressed archives are decompressed to a cache file first.
+    fn open(file_path: &str, format: Option<MboxFormat>, mode: ParseMode) -> Result<Self, MailboxError> {
+        let mmap_path = compression::plain_path(file_path)?;
+        let file = File::open(&mmap_path)?;
+        let metadata = file.metadata()?;
         let file_mmap = unsafe {
             // unsafe block require in case of file is truncated while in use
-            Mmap::map(&File::open(file_path)?)?
+            Mmap::map(&file)?
         };
-        Self::parse(&tokens).map(| emails| MboxFile { emails, file_mmap })
+        let validation = IndexValidation::new(&file_mmap, &metadata);
+        // an index written for a given format is only reused for that format, never in place of detection
+        if let Some(index) = index::load::<EmailFilePtr>(&mmap_path, &file_mmap, &metadata)
+                && format.map_or(index.detected, |format| format == index.format) && mode == index.mode {
+            debug!("Loaded {} emails from index", index.emails.len());
+            return Ok(MboxFile { file_path: file_path.to_string(), mmap_path, emails: index.emails, file_mmap, format: index.format,
+                detected: index.detected, mode, lex_stats: index.lex_stats, report: index.report, validation });
+        }
+        let detected = format.is_none();
+        let format = format.unwrap_or_else(|| MboxFormat::detect(&file_mmap));
+        let nb_chunks = thread::available_parallelism().map_or(1, |n| n.get())
+            .min(file_mmap.len() / PARALLEL_CHUNK_MIN_SIZE)
+            .max(1);
+        let (emails, lex_stats, issues) = Self::parse_chunks(&file_mmap, format, mode, nb_chunks);
+        debug!("Lexed {} messages, {} of {} bytes", lex_stats.messages, lex_stats.bytes, file_mmap.len());
+        let index = MboxIndex { format, detected, mode, lex_stats, report: ParseReport { issues }, emails };
+        if index::save(&mmap_path, &file_mmap, &metadata, &index).is_err() {
+            warn!("Unable to write index of {file_path}");
+        }
+        Ok(MboxFile { file_path: file_path.to_string(), mmap_path, emails: index.emails, file_mmap, format, detected, mode, lex_stats, report: index.report, validation })
     }
 
-    #[instrument(skip_all)]
-    fn parse(tokens: &[Token]) -> Result<Vec<EmailFilePtr>, MailboxError> {
-        let mut emails = vec![];
-        let mut stack: Vec<&Token> = vec![];
-        let mut validator = EmailFilePtrValidator::new();
-
-        for token in tokens {
-            match token {
-                Token::StartEmail(_) if !stack.is_empty() => {
-                    error!("Invalid email missing tokens.");
-                    stack.clear();
-                },
-                Token::End(end_pos) => match stack.pop() {
-                    Some(Token::Boby(start_pos)) => {
-                        let (cte, ct) = if stack.len() > 2 {
-                            match (stack.pop().unwrap(), stack.pop().unwrap()) {
-                                (Token::ContentTransferEncoding(cte), Token::ContentType(ct)) =>
-                                    (cte.as_str(), ct.as_str()),
-                                (a,b) => {
-                                    stack.push(b);
-                                    stack.push(a);
-                                    ("quoted-printable", "text/plain")
-                                }
-                            }
-                        } else {
-                            ("quoted-printable", "text/plain")
-                        };
-                        validator.bodies.push(BodyFilePtr {
-                            content_type: ct.to_string(),
-                            content_transfer_encoding: cte.to_string(),
-                            content: Range{ start: *start_pos as usize, end : *end_pos as usize}
-                        })
-                    },
-                    Some(Token::From(start_pos)) =>
-                        validator.from = Some((*start_pos, *end_pos)),
-                    Some(Token::Subject(start_pos)) =>
-                        validator.subject = Some((*start_pos, *end_pos)),
-                    Some(Token::StartEmail(start_pos)) => {
-                        validator.email = Some((*start_pos, *end_pos));
-                        let tmp = validator;
-                        validator = EmailFilePtrValidator::new();
-                        if let Ok(email_ptr) = tmp.validate() {
-                            emails.push(email_ptr);
-                        }
-                    },
-                    _ => return  Err(MailboxError::MboxParseError),
-                },
-                Token::Date(date) => {
-                    validator.datetime = DateTime::parse_from_rfc2822(&date).ok()
-                            .map(|dt| dt.to_utc());
-                },
-                Token::ContentTransferEncoding(_) | Token::ContentType(_) => (),
-                _ => stack.push(token),
-
-            };
+    /// Parse the messages appended to the file since it was opened or last refreshed. The last known message
+    /// is parsed again as appended bytes may belong to it. When the file was truncated or rewritten, the whole
+    /// mailbox is parsed again.
+    #[instrument(skip(self), fields(file_path = %self.file_path))]
+    pub fn refresh(&mut self) -> Result<Refresh, MailboxError> {
+        let mmap_path = compression::plain_path(&self.file_path)?;
+        let file = File::open(&mmap_path)?;
+        let metadata = file.metadata()?;
+        let file_mmap = unsafe {
+            // unsafe block require in case of file is truncated while in use
+            Mmap::map(&file)?
+        };
+        // the old mapping shares its pages with the new one, so the file is only compared to its checksums
+        if !self.validation.is_appended(&file_mmap, &metadata) {
+            debug!("Mailbox was rewritten, parse it again");
+            // a detected format is detected again, the rewritten file may use another one
+            *self = Self::open(&self.file_path, (!self.detected).then_some(self.format), self.mode)?;
+            return Ok(Refresh::Rewritten);
         }
-        Ok(emails)
-    }
-
-    #[instrument]
-    fn lex(file_path: &str) -> Result<Vec<Token>, MailboxError> {
-        let mut file_reader = BufReader::new(File::open(file_path)?);
-        let mut seek_position:u64 = 0;
-        let mut buf = String::new();
-        let mut tokens = vec![];
-        let mut boundary = None;
-        let mut current_token = Token::Ignore;
-
-        while let Ok(read_size) = file_reader.read_line(&mut buf) && read_size > 0 {
-            let token = Self::lex_line(seek_position, &buf[0..&buf.len()-1], &mut boundary, &current_token);
-            match token {
-                Token::Continuation => (),
-                _ => {
-                    Self::lex_push_current_token(seek_position, &mut tokens, current_token, false);
-                    current_token = token
-                },
-            };
-            seek_position += read_size as u64;
-            buf.clear();
+        let old_len = self.file_mmap.len();
+        if file_mmap.len() == old_len {
+            return Ok(Refresh::Appended(vec![]));
         }
-        Self::lex_push_current_token(seek_position, &mut tokens, current_token, true);
-        Ok(tokens)
-    }
 
-    fn lex_push_current_token(seek_position: u64, tokens: &mut Vec<Token>, current_token: Token, end: bool) {
-        match current_token {
-            Token::StartEmail(_) if end => (),
-            Token::StartEmail(position) if position > 0 => {
-                tokens.push(Token::End(position));
-                tokens.push(current_token);
-            }
-            Token::Ignore | Token::Continuation => (),
-            Token::End(_) | Token::StartEmail(_) | Token::ContentType(_) |
-                Token::Date(_) | Token::ContentTransferEncoding(_) => tokens.push(current_token),
-            Token::From(_) | Token::Subject(_) | Token::Boby(_) => {
-                tokens.push(current_token);
-                tokens.push(Token::End(seek_position));
-            }
+        // messages of the old tail are lexed again, their stats and issues are replaced by the new ones
+        let resume = self.emails.last().map_or(0, |email| email.email.start);
+        let mut old_tail = MboxLexer::with_range(&file_mmap, self.format, resume..old_len);
+        old_tail.by_ref().for_each(drop);
+        let mut lexer = MboxLexer::with_range(&file_mmap, self.format, resume..file_mmap.len());
+        let mut issues = vec![];
+        let tail: Vec<EmailFilePtr> = Self::parse(&file_mmap, lexer.by_ref(), self.mode, &mut issues).collect();
+        self.lex_stats.messages = self.lex_stats.messages - old_tail.stats().messages + lexer.stats().messages;
+        self.lex_stats.bytes = self.lex_stats.bytes - old_tail.stats().bytes + lexer.stats().bytes;
+        self.report.issues.retain(|issue| issue.offset < resume);
+        self.report.issues.extend(issues);
+
+        let last = self.emails.pop();
+        // the last known message failing validation now, the following messages take its id
+        let rewritten = last.as_ref().is_some_and(|last| tail.first().is_none_or(|first| first.email.start != last.email.start));
+        let mut first_new = self.emails.len();
+        if let (Some(last), Some(first)) = (last, tail.first()) && last.email == first.email {
+            first_new += 1;
+        }
+        self.emails.extend(tail);
+        self.file_mmap = file_mmap;
+        self.mmap_path = mmap_path;
+        self.validation = IndexValidation::new(&self.file_mmap, &metadata);
+        debug!("Parsed {} new emails", self.emails.len() - first_new);
+
+        let index = MboxIndex { format: self.format, detected: self.detected, mode: self.mode, lex_stats: self.lex_stats, report: std::mem::take(&mut self.report), emails: std::mem::take(&mut self.emails) };
+        if index::save(&self.mmap_path, &self.file_mmap, &metadata, &index).is_err() {
+            warn!("Unable to write index of {}", self.file_path);
         }
-        if end {
-            tokens.push(Token::End(seek_position));
+        self.emails = index.emails;
+        self.report = index.report;
+        if rewritten {
+            return Ok(Refresh::Rewritten);
         }
+        Ok(Refresh::Appended((first_new..self.emails.len()).collect()))
     }
 
-    fn lex_line(seek_position: u64, buf: &str, boundary: &mut Option<String>, current_token: &Token) -> Token {
-        if buf.starts_with("From ") {
-            *boundary = None;
-            Token::StartEmail(seek_position)
-        } else if buf.starts_with("Subject: ") {
-            Token::Subject(seek_position + 9)
-        } else if buf.starts_with("From: ") {
-            Token::From(seek_position + 6)
-        } else if buf.starts_with("Date: ") {
-            Token::Date(buf[6..].to_string())
-        } else if buf.starts_with("Content-Transfer-Encoding: ") {
-            Token::ContentTransferEncoding(buf[27..].to_string())
-        } else if buf.starts_with("Content-Type: ") || buf.starts_with("	boundary=") {
-            if let Some(nb) = buf.find("boundary=") {
-                let tmp_boundary = &buf[(nb+10)..];
-                if let Some(idx) = tmp_boundary.find('"') {
-                    *boundary = Some((&tmp_boundary[0..idx]).to_string())
-                }
-                Token::Ignore
-            } else {
-                Token::ContentType(buf[14..].to_string())
-            }
-        } else if let Some(boundary) = &boundary && buf.starts_with(boundary) {
-            Token::End(seek_position)
-        } else {
-            match *current_token {
-                Token::ContentTransferEncoding(_) if buf.is_empty() => Token::Boby(seek_position),
-                Token::Boby(_) => Token::Continuation,
-                _ if boundary.is_none() && buf.is_empty() => Token::Boby(seek_position),
-                _ if buf.starts_with(" ") => Token::Continuation,
-                _ => Token::Ignore
-            }
-        }
+    /// Format variant the mailbox is read with, given when opening it or detected.
+    pub fn format(&self) -> MboxFormat {
+        self.format
+    }
+
+    /// Messages and bytes of the file consumed by the lexer, messages failing validation included.
+    pub fn lex_stats(&self) -> LexStats {
+        self.lex_stats
     }
 
-    fn get_header(&self, range: &Range<usize>) -> Result<String, MailboxError> {
-        let decoder = Decoder::new().too_long_encoded_word_strategy(RecoverStrategy::Skip);
-        decoder.decode(&self.file_mmap[range.start..range.end])
-            .map(|value| value.replace("\n", ""))
-            .or(Err(MailboxError::EncodedWordDecodeError))
+    /// Messages skipped or repaired when the mailbox was parsed.
+    pub fn parse_report(&self) -> &ParseReport {
+        &self.report
     }
 
-    fn get_body(&self, body_ptr: &BodyFilePtr) -> Result<String, MailboxError> {
-        if let Ok(decoded) = decode(&self.file_mmap[body_ptr.content.start..body_ptr.content.end], ParseMode::Robust) {
-            String::from_utf8(decoded).or(Err(MailboxError::UTF8EncodeError))
-        } else {
-            Err(MailboxError::DecodeQuotedPrintableError)
+    /// Lex and parse `nb_chunks` parts of the file in parallel threads, results are merged in file order
+    /// so emails get the same ids as with a sequential parsing.
+    #[instrument(skip(data))]
+    fn parse_chunks(data: &[u8], format: MboxFormat, mode: ParseMode, nb_chunks: usize) -> (Vec<EmailFilePtr>, LexStats, Vec<ParseIssue>) {
+        if nb_chunks <= 1 || format.use_content_length() {
+            let mut lexer = MboxLexer::new(data, format);
+            let mut issues = vec![];
+            let emails = Self::parse(data, lexer.by_ref(), mode, &mut issues).collect();
+            return (emails, lexer.stats(), issues);
         }
+        thread::scope(|scope| {
+            let handles: Vec<_> = MboxLexer::split_chunks(data, nb_chunks).into_iter()
+                .map(|chunk| scope.spawn(move || {
+                    let mut lexer = MboxLexer::with_range(data, format, chunk);
+                    let mut issues = vec![];
+                    let emails: Vec<EmailFilePtr> = Self::parse(data, lexer.by_ref(), mode, &mut issues).collect();
+                    (emails, lexer.stats(), issues)
+                }))
+                .collect();
+            let mut emails = vec![];
+            let mut lex_stats = LexStats::default();
+            let mut issues = vec![];
+            for handle in handles {
+                let (chunk_emails, chunk_stats, chunk_issues) = handle.join().expect("Mbox parsing thread panicked");
+                emails.extend(chunk_emails);
+                lex_stats.messages += chunk_stats.messages;
+                lex_stats.bytes += chunk_stats.bytes;
+                issues.extend(chunk_issues);
+            }
+            (emails, lex_stats, issues)
+        })
+    }
+
+    /// Parse each message range as soon as the lexer has found its end, adding skipped or repaired messages to `issues`.
+    #[instrument(skip_all)]
+    fn parse<'a>(data: &'a [u8], messages: impl Iterator<Item = Range<usize>> + 'a, mode: ParseMode, issues: &'a mut Vec<ParseIssue>)
+            -> impl Iterator<Item = EmailFilePtr> + 'a {
+        messages.filter_map(move |email| EmailFilePtr::parse_reporting(data, email, mode, issues))
+    }
+
+    fn reader(&self) -> MessageReader<'_> {
+        MessageReader::new(&self.file_mmap, Some(self.format))
     }
 
 }
@@ -267,27 +219,8 @@ impl MailStorageRepository for MboxFile {
     type EmailId = usize;
 
     fn get_email(&self, id: &Self::EmailId) -> Result<Email<Self::EmailId>, MailboxError> {
-        if let Some(email_ptr) = self.emails.get(*id) {
-            let email = Email {
-                id: *id,
-                from: self.get_header(&email_ptr.from)?,
-                datetime: email_ptr.datetime,
-                subject: self.get_header(&email_ptr.subject)?,
-                body_text: email_ptr.bodies.iter()
-                            .filter(|bp| !bp.is_html())
-                            .next()
-                            .map(|bp| self.get_body(bp).ok())
-                            .flatten(),
-                body_html: email_ptr.bodies.iter()
-                            .filter(|bp| bp.is_html())
-                            .next()
-                            .map(|bp| self.get_body(bp).ok())
-                            .flatten()
-            };
-            Ok(email)
-        } else {
-            Err(MailboxError::EmailNotFound)
-        }
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        self.reader().email(*id, email_ptr)
     }
 
     fn count_emails(&self) -> Result<usize, MailboxError> {
@@ -298,6 +231,46 @@ impl MailStorageRepository for MboxFile {
         EmailIterator { idx: 0, mbox: &self, duration: Duration::new(0, 0) }
     }
 
+    fn email_view(&self, id: &Self::EmailId) -> Result<EmailView<'_, Self::EmailId>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        Ok(EmailView::new(*id, Cow::Borrowed(&self.file_mmap[..]), Some(self.format), email_ptr))
+    }
+
+    fn email_views(&self) -> impl Iterator<Item = EmailView<'_, Self::EmailId>> {
+        self.emails.iter().enumerate()
+            .map(|(id, email_ptr)| EmailView::new(id, Cow::Borrowed(&self.file_mmap[..]), Some(self.format), email_ptr))
+    }
+
+    fn header(&self, id: &Self::EmailId, name: &str) -> Result<Option<String>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        self.reader().header(email_ptr, name)
+    }
+
+    fn raw_email(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        Ok(self.reader().raw_email(email_ptr))
+    }
+
+    fn raw_headers(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        Ok(self.reader().raw_headers(email_ptr))
+    }
+
+    fn raw_part(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        self.reader().raw_part(email_ptr, index)
+    }
+
+    fn attachments(&self, id: &Self::EmailId) -> Result<Vec<Attachment>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        Ok(self.reader().attachments(email_ptr))
+    }
+
+    fn attachment_content(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        let email_ptr = self.emails.get(*id).ok_or(MailboxError::EmailNotFound)?;
+        self.reader().attachment_content(email_ptr, index)
+    }
+
 }
 
 struct EmailIterator<'a> {
@@ -309,10 +282,16 @@ struct EmailIterator<'a> {
 impl<'a> Iterator for EmailIterator<'a> {
     type Item = Email<usize>;
 
+    /// Next email, emails failing to be read are skipped rather than ending the iteration.
     fn next(&mut self) -> Option<Self::Item> {
         let start = Instant::now();
-        let res = self.mbox.get_email(&self.idx).ok();
-        self.idx += 1;
+        let mut res = None;
+        while res.is_none() && self.idx < self.mbox.emails.len() {
+            res = self.mbox.get_email(&self.idx)
+                .inspect_err(|e| warn!("Skip email {} : {e}", self.idx))
+                .ok();
+            self.idx += 1;
+        }
         self.duration += start.elapsed();
         if res.is_none() {
             debug!("Get emails content total duration : {:?}", self.duration);
@@ -323,8 +302,11 @@ impl<'a> Iterator for EmailIterator<'a> {
 
 #[cfg(test)]
 mod tests {
+    use chrono::DateTime;
     use tracing_test::traced_test;
 
+    use crate::storage::report::{ParseIssueKind, ParseReason};
+
     use super::*;
 
     #[test]
@@ -335,14 +317,15 @@ mod tests {
         assert!(datetime.is_some());
     }
 
+    fn read_dataset(file_path: &str) -> Vec<u8> {
+        std::fs::read(file_path).unwrap()
+    }
+
     #[test]
     #[traced_test]
     fn test_seek_positions() {
-        let tokens = MboxFile::lex("datasets/test_seek_positions.mbox");
-        assert!(tokens.is_ok());
-        let res = MboxFile::parse(&tokens.unwrap());
-        assert!(res.is_ok());
-        let emails = res.unwrap();
+        let data = read_dataset("datasets/test_seek_positions.mbox");
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(&data, MboxLexer::new(&data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).collect();
         println!("emails len : {}", emails.len());
         assert_eq!(1, emails.len());
         assert_eq!(25, emails[0].email.start);
@@ -350,65 +333,116 @@ mod tests {
 
     #[test]
     fn test_parse_file() {
-        let tokens = MboxFile::lex("datasets/test_lex.mbox");
-        assert!(tokens.is_ok());
-        let emails = MboxFile::parse(&tokens.unwrap());
-        assert!(emails.is_ok());
-        assert_eq!(3, emails.unwrap().len());
+        let data = read_dataset("datasets/test_lex.mbox");
+        assert_eq!(3, MboxFile::parse(&data, MboxLexer::new(&data, MboxFormat::Mboxrd), ParseMode::Strict, &mut vec![]).count());
     }
 
     #[test]
-    fn test_lex_file() {
-        let tokens = MboxFile::lex("datasets/test_lex.mbox");
-        assert!(tokens.is_ok());
-        // for token in tokens.as_ref().unwrap() {
-        //     println!("{token}")
-        // }
-        assert!(tokens.is_ok());
+    fn test_parse_headers_case_insensitive() {
+        let data = b"From toto@example.com\nfrom: bla <bla@bla.org>\nDATE: Mon, 4 Aug 2025 11:56:07 +0800\nsubject:  Hello\n world\n\nLorem ipsum\n";
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).collect();
+        assert_eq!(1, emails.len());
+        assert_eq!(b"Hello\n world", &data[emails[0].subject.clone().unwrap()]);
+        assert_eq!(b"bla <bla@bla.org>", &data[emails[0].from.start..emails[0].from.end]);
+        assert_eq!(3, emails[0].mime.headers.len());
     }
 
     #[test]
-    fn test_lex_line_from() {
-        let mut boundary = None;
-        let token = MboxFile::lex_line(0, "From toto@example.com\n", &mut boundary, &Token::Ignore);
-        match token {
-            Token::StartEmail(pos) => assert_eq!(pos, 0),
-            _ => panic!("Expected StartEmail token"),
-        }
+    fn test_unknown_transfer_encoding() {
+        let data = b"From toto@example.com\nFrom: bla <bla@bla.org>\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: Hello\nContent-Transfer-Encoding: x-gzip\n\nLorem ipsum\n";
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).collect();
+        let email = MessageReader::new(data, Some(MboxFormat::Mboxo)).email(0, &emails[0]).unwrap();
+        assert_eq!(Some("Hello"), email.subject.as_deref());
+        assert_eq!(None, email.body_text);
+        assert_eq!(Some(MailboxError::UnknownTransferEncodingError), email.body_error);
     }
 
     #[test]
-    fn test_lex_line_subject() {
-        let mut boundary = None;
-        let token = MboxFile::lex_line(10, "Subject: Hello\n", &mut boundary, &Token::Ignore);
-        match token {
-            Token::Subject(pos) => assert_eq!(pos, 19), // 10 + 9
-            _ => panic!("Expected Subject token"),
-        }
+    fn test_malformed_encoded_word() {
+        let data = b"From toto@example.com\nFrom: bla <bla@bla.org>\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: Hello\n\
+            Message-ID: <=?utf-8?B?@@@?=@example.com>\nList-Id: =?utf-8?B?@@@?=\n\nLorem ipsum\n";
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).collect();
+        let email = MessageReader::new(data, Some(MboxFormat::Mboxo)).email(0, &emails[0]).unwrap();
+        assert_eq!(Some("<=?utf-8?B?@@@?=@example.com>"), email.message_id.as_deref());
+        assert_eq!(Some("=?utf-8?B?@@@?="), email.list_id.as_deref());
     }
 
     #[test]
-    fn test_lex_line_date() {
-        let mut boundary = None;
-        let token = MboxFile::lex_line(5, "Date: Mon, 1 Jan 2020 00:00:00 +0000\n", &mut boundary, &Token::Ignore);
-        match token {
-            Token::Date(ref s) => assert_eq!(s, "Mon, 1 Jan 2020 00:00:00 +0000\n"),
-            _ => panic!("Expected Date token"),
-        }
+    fn test_parse_nested_multipart_bodies() {
+        let data = read_dataset("datasets/test_lex.mbox");
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(&data, MboxLexer::new(&data, MboxFormat::Mboxrd), ParseMode::Strict, &mut vec![]).collect();
+        assert_eq!("multipart/alternative", emails[0].mime.content_type);
+        assert_eq!(1, emails[0].bodies.len());
+        assert_eq!("quoted-printable", emails[0].bodies[0].content_transfer_encoding);
+        assert!(data[emails[0].bodies[0].content.clone()].starts_with(b"+1 (binding)"));
+        assert!(data[emails[0].bodies[0].content.clone()].ends_with(b"Thanks!\n>\n"));
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let data = b"From a\nFrom: a@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\n\nno subject\n\
+            From b\nFrom: b@b.c\nDate: yesterday\nSubject: b\n\nbody\n\
+            From c\nFrom: c@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: c\nContent-Type: multipart/mixed; boundary=x\n\n--x\n\ntruncated\n";
+        let mut issues = vec![];
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut issues).collect();
+        assert_eq!(1, emails.len());
+        let report = ParseReport { issues };
+        let offset = |separator: &[u8]| data.windows(separator.len()).position(|w| w == separator).unwrap();
+        assert_eq!(2, report.skipped());
+        assert_eq!(1, report.repaired());
+        assert_eq!(ParseIssue::new(0, ParseIssueKind::Skipped, ParseReason::MissingField, Some("Subject")), report.issues[0]);
+        assert_eq!(ParseIssue::new(offset(b"From b\n"), ParseIssueKind::Skipped, ParseReason::InvalidField, Some("Date")), report.issues[1]);
+        assert_eq!(ParseIssue::new(offset(b"From c\n"), ParseIssueKind::Repaired, ParseReason::MissingCloseDelimiter, None), report.issues[2]);
     }
 
     #[test]
-    fn test_lex_line_content_type_with_boundary() {
-        let mut boundary = None;
-        let token = MboxFile::lex_line(0, "Content-Type: multipart/mixed; boundary=\"abc123\"\n", &mut boundary, &Token::Ignore);
-        assert!(matches!(token, Token::Ignore));
-        assert_eq!(boundary, Some("abc123".to_string()));
+    fn test_parse_lenient() {
+        let data = b"From a@b.c Mon Aug  4 11:56:07 2025\nFrom: a@b.c\nDate: yesterday\n\nno subject\n\
+            From b@b.c\nFrom: b@b.c\nReceived: from relay; Mon, 4 Aug 2025 11:56:07 +0800\nReceived: from mx; Mon, 4 Aug 2025 11:57:07 +0800\nSubject: b\n\nbody\n\
+            From c@b.c\nSubject: c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\n\nno sender\n\
+            From d@b.c\nFrom: d@b.c\nSubject: d\n\nno date\n";
+        assert!(MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).next().is_none());
+        let mut issues = vec![];
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Lenient, &mut issues).collect();
+        assert_eq!(2, emails.len());
+        assert_eq!(None, emails[0].subject);
+        assert_eq!(DateTime::parse_from_rfc3339("2025-08-04T11:56:07Z").unwrap(), emails[0].datetime);
+        assert_eq!(DateTime::parse_from_rfc3339("2025-08-04T03:57:07Z").unwrap(), emails[1].datetime);
+        let report = ParseReport { issues };
+        assert_eq!(2, report.skipped());
+        assert_eq!(2, report.repaired());
+        assert_eq!(ParseIssue::new(0, ParseIssueKind::Repaired, ParseReason::MissingField, Some("Subject")), report.issues[0]);
+        assert_eq!(ParseIssue::new(0, ParseIssueKind::Repaired, ParseReason::InvalidField, Some("Date")), report.issues[1]);
+
+        let data = b"From a@b.c Mon Aug  4 11:56:07 2025 +0200\nFrom: a@b.c\n\nzone\nFrom a@b.c Mon, 4 Aug 2025 11:56:07 +0800\nFrom: a@b.c\n\nrfc 2822\n";
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Lenient, &mut vec![]).collect();
+        assert_eq!(DateTime::parse_from_rfc3339("2025-08-04T11:56:07+02:00").unwrap(), emails[0].datetime);
+        assert_eq!(DateTime::parse_from_rfc3339("2025-08-04T11:56:07+08:00").unwrap(), emails[1].datetime);
+    }
+
+    #[test]
+    fn test_parse_chunks_same_as_sequential() {
+        let data = read_dataset("datasets/test_emails_1000.mbox");
+        let (sequential, sequential_stats, sequential_issues) = MboxFile::parse_chunks(&data, MboxFormat::Mboxo, ParseMode::Strict, 1);
+        let (parallel, parallel_stats, parallel_issues) = MboxFile::parse_chunks(&data, MboxFormat::Mboxo, ParseMode::Strict, 6);
+        assert_eq!(sequential_stats, parallel_stats);
+        assert_eq!(sequential_issues, parallel_issues);
+        assert_eq!(sequential.len(), parallel.len());
+        for (sequential, parallel) in sequential.iter().zip(&parallel) {
+            assert_eq!(sequential.email, parallel.email);
+            assert_eq!(sequential.subject, parallel.subject);
+        }
     }
 
     #[test]
-    fn test_lex_line_ignore() {
-        let mut boundary = None;
-        let token = MboxFile::lex_line(0, "Random header\n", &mut boundary, &Token::Ignore);
-        assert!(matches!(token, Token::Ignore));
+    fn test_parse_content_length() {
+        let data = b"From a\nFrom: a@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: a\nContent-Length: 12\n\nFrom inside\n\nFrom c\nFrom: c@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: c\n\nbody\n";
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxcl2), ParseMode::Strict, &mut vec![]).collect();
+        assert_eq!(2, emails.len());
+        assert_eq!(Range { start: 0, end: 100 }, emails[0].email);
+        assert_eq!(b"From inside\n\n", &data[emails[0].bodies[0].content.clone()]);
+        let emails: Vec<EmailFilePtr> = MboxFile::parse(data, MboxLexer::new(data, MboxFormat::Mboxo), ParseMode::Strict, &mut vec![]).collect();
+        assert_eq!(2, emails.len());
+        assert_eq!(Range { start: 0, end: 87 }, emails[0].email);
     }
 }
@@ -0,0 +1,135 @@
+use std::borrow::Cow;
+
+use memchr::memmem;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::header::{next_line, HeaderBlock};
+
+/// Variants of the mbox format, which differ on how `From ` lines in bodies are handled.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum MboxFormat {
+    /// Body lines starting with `From ` are quoted as `>From `, not reversible.
+    Mboxo,
+    /// Body lines matching `>*From ` get one more `>`, reversed when reading bodies.
+    Mboxrd,
+    /// Mboxo quoting, messages length given by their `Content-Length` header.
+    Mboxcl,
+    /// No quoting, messages length given by their `Content-Length` header.
+    Mboxcl2,
+}
+
+impl MboxFormat {
+
+    /// Guess the format of a mailbox : `Content-Length` variants when the first message has a
+    /// `Content-Length` ending right before the next separator, mboxrd when `>>From ` lines exist.
+    pub fn detect(data: &[u8]) -> Self {
+        if Self::has_valid_content_length(data) {
+            if contains(data, b"\n>From ") { MboxFormat::Mboxcl } else { MboxFormat::Mboxcl2 }
+        } else if contains(data, b"\n>>From ") {
+            MboxFormat::Mboxrd
+        } else {
+            MboxFormat::Mboxo
+        }
+    }
+
+    fn has_valid_content_length(data: &[u8]) -> bool {
+        if !data.starts_with(b"From ") {
+            return false;
+        }
+        let (_, next) = next_line(data, 0);
+        let end = memmem::find(&data[next..], b"\nFrom ").map_or(data.len(), |pos| next + pos + 1);
+        let block = HeaderBlock::parse(&data[..end], 0);
+        content_length(data, &block)
+            .and_then(|len| block.body_start.checked_add(len))
+            .is_some_and(|end| is_message_end(data, end))
+    }
+
+    pub fn use_content_length(&self) -> bool {
+        matches!(self, MboxFormat::Mboxcl | MboxFormat::Mboxcl2)
+    }
+
+    /// Reverse the `From ` quoting of the format on a raw message content.
+    pub fn unescape<'a>(&self, content: &'a [u8]) -> Cow<'a, [u8]> {
+        if *self != MboxFormat::Mboxrd || !(content.starts_with(b">") || contains(content, b"\n>")) {
+            return Cow::Borrowed(content);
+        }
+        let lines = || {
+            let mut pos = 0;
+            std::iter::from_fn(move || (pos < content.len()).then(|| {
+                let (_, next) = next_line(content, pos);
+                let line = &content[pos..next];
+                pos = next;
+                line
+            }))
+        };
+        if !lines().any(is_quoted_from) {
+            return Cow::Borrowed(content);
+        }
+        let mut res = Vec::with_capacity(content.len());
+        for line in lines() {
+            res.extend_from_slice(if is_quoted_from(line) { &line[1..] } else { line });
+        }
+        Cow::Owned(res)
+    }
+
+}
+
+/// Whether `line` is a `From ` line quoted by one or more `>`.
+fn is_quoted_from(line: &[u8]) -> bool {
+    let quotes = line.iter().take_while(|&&c| c == b'>').count();
+    quotes > 0 && line[quotes..].starts_with(b"From ")
+}
+
+/// Value of the `Content-Length` header of a message.
+pub fn content_length(data: &[u8], block: &HeaderBlock) -> Option<usize> {
+    block.find(data, "Content-Length")
+        .and_then(|header| String::from_utf8_lossy(&header.unfolded_value(data)).trim().parse().ok())
+}
+
+/// Whether `pos` is the end of the file or of a message : only line endings before the next `From ` line.
+pub fn is_message_end(data: &[u8], pos: usize) -> bool {
+    if pos > data.len() {
+        return false;
+    }
+    let rest = &data[pos..];
+    let separator = rest.iter().take_while(|&&c| c == b'\r' || c == b'\n').count();
+    separator == rest.len() || ((separator > 0 || pos == 0 || data[pos - 1] == b'\n') && rest[separator..].starts_with(b"From "))
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    memmem::find(data, needle).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(MboxFormat::Mboxrd, MboxFormat::detect(&std::fs::read("datasets/test_lex.mbox").unwrap()));
+        assert_eq!(MboxFormat::Mboxo, MboxFormat::detect(b"From a\nSubject: a\n\n>From b\nFrom c\n\nbody\n"));
+        assert_eq!(MboxFormat::Mboxcl2, MboxFormat::detect(b"From a\nContent-Length: 12\n\nFrom inside\n\nFrom c\n\nbody\n"));
+        assert_eq!(MboxFormat::Mboxcl, MboxFormat::detect(b"From a\nContent-Length: 13\n\n>From inside\n\nFrom c\n\nbody\n"));
+        assert_eq!(MboxFormat::Mboxo, MboxFormat::detect(b"From a\nContent-Length: 3\n\nFrom inside\n\nFrom c\n\nbody\n"));
+        assert_eq!(MboxFormat::Mboxo, MboxFormat::detect(b"From a\nContent-Length: 18446744073709551615\n\nbody\n\nFrom c\n\nbody\n"));
+    }
+
+    #[test]
+    fn test_unescape_mboxrd() {
+        let content = b">From A From Line\n>>From A >From Line\nFrom\n> From\n>>>>>From x";
+        assert_eq!(b"From A From Line\n>From A >From Line\nFrom\n> From\n>>>>From x", MboxFormat::Mboxrd.unescape(content).as_ref());
+        assert!(matches!(MboxFormat::Mboxo.unescape(content), Cow::Borrowed(_)));
+        assert!(matches!(MboxFormat::Mboxrd.unescape(b"no quoted line"), Cow::Borrowed(_)));
+        assert!(matches!(MboxFormat::Mboxrd.unescape(b"reply\n> quoted\n>> From\n"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_is_message_end() {
+        let data = b"body\n\nFrom next\n";
+        assert!(is_message_end(data, 5));
+        assert!(is_message_end(data, 6));
+        assert!(!is_message_end(data, 3));
+        assert!(is_message_end(data, data.len()));
+        assert!(!is_message_end(data, data.len() + 1));
+    }
+}
@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+/// Position of a header field in the mailbox : field name and raw value, still folded,
+/// without the line ending.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HeaderFilePtr {
+    pub name: Range<usize>,
+    pub value: Range<usize>,
+}
+
+impl HeaderFilePtr {
+
+    pub fn name<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.name.start..self.name.end]
+    }
+
+    pub fn is(&self, data: &[u8], name: &str) -> bool {
+        self.name(data).eq_ignore_ascii_case(name.as_bytes())
+    }
+
+    pub fn raw_value<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.value.start..self.value.end]
+    }
+
+    pub fn unfolded_value(&self, data: &[u8]) -> Vec<u8> {
+        unfold(self.raw_value(data))
+    }
+
+}
+
+/// Header section of a message.
+#[derive(Serialize, Debug, Default)]
+pub struct HeaderBlock {
+    pub headers: Vec<HeaderFilePtr>,
+    /// Position of the first byte following the blank line which ends the header section.
+    pub body_start: usize,
+}
+
+impl HeaderBlock {
+
+    /// Parse the header section at the start of `buf`, `offset` being the position of `buf` in
+    /// the mailbox so the ranges can be used directly on the whole file.
+    /// Lines which are neither a field nor a continuation (mbox `From ` separator, garbage) are skipped.
+    pub fn parse(buf: &[u8], offset: usize) -> Self {
+        let mut headers: Vec<HeaderFilePtr> = vec![];
+        let mut pos = 0;
+
+        while pos < buf.len() {
+            let (line, next) = next_line(buf, pos);
+            if line.is_empty() {
+                return HeaderBlock { headers, body_start: offset + next };
+            }
+            if buf[line.start] == b' ' || buf[line.start] == b'\t' {
+                if let Some(last) = headers.last_mut() {
+                    last.value.end = offset + line.end;
+                }
+            } else if let Some(header) = Self::parse_field(buf, line) {
+                headers.push(HeaderFilePtr {
+                    name: Range { start: offset + header.name.start, end: offset + header.name.end },
+                    value: Range { start: offset + header.value.start, end: offset + header.value.end },
+                });
+            }
+            pos = next;
+        }
+        HeaderBlock { headers, body_start: offset + buf.len() }
+    }
+
+    fn parse_field(buf: &[u8], line: Range<usize>) -> Option<HeaderFilePtr> {
+        let colon = line.start + buf[line.clone()].iter().position(|&c| c == b':')?;
+        let mut name_end = colon;
+        while name_end > line.start && is_wsp(buf[name_end - 1]) {
+            name_end -= 1;
+        }
+        if name_end == line.start || !buf[line.start..name_end].iter().all(|&c| (33..=126).contains(&c)) {
+            return None;
+        }
+        let mut value_start = colon + 1;
+        while value_start < line.end && is_wsp(buf[value_start]) {
+            value_start += 1;
+        }
+        Some(HeaderFilePtr {
+            name: Range { start: line.start, end: name_end },
+            value: Range { start: value_start, end: line.end },
+        })
+    }
+
+    pub fn find(&self, data: &[u8], name: &str) -> Option<&HeaderFilePtr> {
+        self.headers.iter().find(|header| header.is(data, name))
+    }
+
+}
+
+/// Line starting at `pos` without its line ending (LF or CRLF), and position of the next line.
+pub(crate) fn next_line(buf: &[u8], pos: usize) -> (Range<usize>, usize) {
+    let (line_end, next) = match buf[pos..].iter().position(|&c| c == b'\n') {
+        Some(idx) => (pos + idx, pos + idx + 1),
+        None => (buf.len(), buf.len()),
+    };
+    if line_end > pos && buf[line_end - 1] == b'\r' {
+        (Range { start: pos, end: line_end - 1 }, next)
+    } else {
+        (Range { start: pos, end: line_end }, next)
+    }
+}
+
+fn is_wsp(c: u8) -> bool {
+    c == b' ' || c == b'\t'
+}
+
+/// Unfold a header value (RFC 5322 section 2.2.3) : line breaks followed by whitespace are removed.
+pub fn unfold(raw: &[u8]) -> Vec<u8> {
+    raw.iter().copied().filter(|&c| c != b'\r' && c != b'\n').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_case_insensitive_names() {
+        let buf = b"subject: Hello\nFROM: bla <bla@bla.org>\nDate: Mon, 1 Jan 2020 00:00:00 +0000\n\nbody";
+        let block = HeaderBlock::parse(buf, 0);
+        assert_eq!(3, block.headers.len());
+        assert_eq!(b"Hello", block.find(buf, "Subject").unwrap().raw_value(buf));
+        assert_eq!(b"bla <bla@bla.org>", block.find(buf, "from").unwrap().raw_value(buf));
+        assert!(block.find(buf, "To").is_none());
+        assert_eq!(b"body", &buf[block.body_start..]);
+    }
+
+    #[test]
+    fn test_parse_whitespace_around_colon() {
+        let buf = b"Subject:Hello\nX-Test \t:  \tvalue\n\n";
+        let block = HeaderBlock::parse(buf, 0);
+        assert_eq!(b"Hello", block.headers[0].raw_value(buf));
+        assert_eq!(b"X-Test", block.headers[1].name(buf));
+        assert_eq!(b"value", block.headers[1].raw_value(buf));
+    }
+
+    #[test]
+    fn test_parse_folded_value() {
+        let buf = b"Subject: Hello\n world\n\tagain\nTo: a@b.c\n\n";
+        let block = HeaderBlock::parse(buf, 0);
+        let subject = block.find(buf, "subject").unwrap();
+        assert_eq!(b"Hello\n world\n\tagain", subject.raw_value(buf));
+        assert_eq!(b"Hello world\tagain".to_vec(), subject.unfolded_value(buf));
+        assert_eq!(b"a@b.c", block.find(buf, "to").unwrap().raw_value(buf));
+    }
+
+    #[test]
+    fn test_parse_crlf_and_offset() {
+        let buf = b"From toto@example.com\r\nSubject: Hello\r\n\r\nbody\r\n";
+        let block = HeaderBlock::parse(buf, 100);
+        assert_eq!(1, block.headers.len());
+        assert_eq!(Range { start: 132, end: 137 }, block.headers[0].value);
+        assert_eq!(141, block.body_start);
+    }
+}
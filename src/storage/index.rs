@@ -0,0 +1,192 @@
+use std::{fs::{File, Metadata}, io::Write, path::Path, time::UNIX_EPOCH};
+
+use bincode::Options;
+use memmap2::Mmap;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::storage::{format::MboxFormat, lexer::LexStats, message::ParseMode, report::ParseReport, write_atomically, MailboxError};
+
+const INDEX_MAGIC: &[u8; 8] = b"MBOXIDX\0";
+/// Version of the sidecar layout, to increase on any change of the serialized structures.
+const INDEX_VERSION: u32 = 7;
+/// Size of the head and tail of the mailbox covered by the checksums.
+const CHECKSUM_SIZE: usize = 64 * 1024;
+
+/// Parsed content of a mailbox, persisted next to it to avoid parsing it again on each start.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MboxIndex<T> {
+    pub format: MboxFormat,
+    /// Whether `format` was detected from the content rather than given when opening the mailbox.
+    pub detected: bool,
+    pub mode: ParseMode,
+    pub lex_stats: LexStats,
+    pub report: ParseReport,
+    pub emails: Vec<T>,
+}
+
+/// State of the mailbox when its index was written or when it was opened.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct IndexValidation {
+    file_size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    head_checksum: u64,
+    tail_checksum: u64,
+}
+
+impl IndexValidation {
+
+    pub(crate) fn new(data: &[u8], metadata: &Metadata) -> Self {
+        let mtime = metadata.modified().ok()
+            .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        IndexValidation {
+            file_size: metadata.len(),
+            mtime_secs: mtime.as_secs(),
+            mtime_nanos: mtime.subsec_nanos(),
+            head_checksum: fnv1a(&data[..CHECKSUM_SIZE.min(data.len())]),
+            tail_checksum: fnv1a(&data[data.len().saturating_sub(CHECKSUM_SIZE)..]),
+        }
+    }
+
+    /// Whether `data` is this mailbox with bytes appended : its first `file_size` bytes have the same head
+    /// and tail checksums. A mailbox of the same size must be unchanged, modification time included.
+    pub(crate) fn is_appended(&self, data: &[u8], metadata: &Metadata) -> bool {
+        let Ok(len) = usize::try_from(self.file_size) else {
+            return false;
+        };
+        if data.len() <= len {
+            return data.len() == len && *self == IndexValidation::new(data, metadata);
+        }
+        let data = &data[..len];
+        fnv1a(&data[..CHECKSUM_SIZE.min(len)]) == self.head_checksum
+            && fnv1a(&data[len.saturating_sub(CHECKSUM_SIZE)..]) == self.tail_checksum
+    }
+
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexFile<I> {
+    validation: IndexValidation,
+    index: I,
+}
+
+/// Path of the index sidecar of a mailbox.
+pub fn index_path(mbox_path: &str) -> String {
+    format!("{mbox_path}.idx")
+}
+
+/// Load the index of the mailbox at `mbox_path`, `None` when it is missing, unreadable or stale.
+#[instrument(skip(data, metadata))]
+pub fn load<T: DeserializeOwned>(mbox_path: &str, data: &[u8], metadata: &Metadata) -> Option<MboxIndex<T>> {
+    let index_mmap = unsafe {
+        // unsafe block require in case of file is truncated while in use
+        Mmap::map(&File::open(index_path(mbox_path)).ok()?).ok()?
+    };
+    let header_len = INDEX_MAGIC.len() + size_of::<u32>();
+    if index_mmap.len() < header_len || &index_mmap[..INDEX_MAGIC.len()] != INDEX_MAGIC
+            || index_mmap[INDEX_MAGIC.len()..header_len] != INDEX_VERSION.to_le_bytes() {
+        debug!("Mbox index has an unknown format or version");
+        return None;
+    }
+    // encoding of `bincode::serialize`, with allocations bounded by the sidecar size against corrupt lengths
+    let index_file: IndexFile<MboxIndex<T>> = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(index_mmap.len() as u64)
+        .deserialize(&index_mmap[header_len..]).ok()?;
+    if index_file.validation != IndexValidation::new(data, metadata) {
+        debug!("Mbox index is stale");
+        return None;
+    }
+    Some(index_file.index)
+}
+
+/// Write the index of the mailbox at `mbox_path`, replacing the previous one atomically.
+#[instrument(skip(data, metadata, index))]
+pub fn save<T: Serialize>(mbox_path: &str, data: &[u8], metadata: &Metadata, index: &MboxIndex<T>) -> Result<(), MailboxError> {
+    let index_file = IndexFile { validation: IndexValidation::new(data, metadata), index };
+    write_atomically(Path::new(&index_path(mbox_path)), false, MailboxError::MboxIndexError, |writer| {
+        writer.write_all(INDEX_MAGIC).or(Err(MailboxError::MboxIndexError))?;
+        writer.write_all(&INDEX_VERSION.to_le_bytes()).or(Err(MailboxError::MboxIndexError))?;
+        bincode::serialize_into(writer, &index_file).or(Err(MailboxError::MboxIndexError))
+    })
+}
+
+/// 64 bits FNV-1a hash, stable across Rust versions unlike `DefaultHasher`.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &c| (hash ^ c as u64).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::storage::tmp_dir;
+
+    use super::*;
+
+    fn read(path: &str) -> (Vec<u8>, Metadata) {
+        (fs::read(path).unwrap(), fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tmp_dir(&[("a.mbox", "From a\n\nbody\n")]);
+        let path = dir.path().join("a.mbox").to_string_lossy().to_string();
+        let (data, metadata) = read(&path);
+        let index = MboxIndex { format: MboxFormat::Mboxrd, detected: true, mode: ParseMode::Strict, lex_stats: LexStats { messages: 1, bytes: 13 }, report: ParseReport::default(), emails: vec![1usize, 2, 3] };
+        save(&path, &data, &metadata, &index).unwrap();
+        let loaded: MboxIndex<usize> = load(&path, &data, &metadata).unwrap();
+        assert_eq!(index.emails, loaded.emails);
+        assert_eq!(MboxFormat::Mboxrd, loaded.format);
+        assert_eq!(index.lex_stats, loaded.lex_stats);
+    }
+
+    #[test]
+    fn test_is_appended() {
+        let dir = tmp_dir(&[("a.mbox", "From a\n\nbody\n")]);
+        let path = dir.path().join("a.mbox").to_string_lossy().to_string();
+        let (data, metadata) = read(&path);
+        let validation = IndexValidation::new(&data, &metadata);
+        assert!(validation.is_appended(&data, &metadata));
+        assert!(validation.is_appended(b"From a\n\nbody\nFrom b\n", &metadata));
+        assert!(!validation.is_appended(b"From a\n\nbodY\nFrom b\n", &metadata));
+        assert!(!validation.is_appended(b"From a\n\nbodY\n", &metadata));
+        assert!(!validation.is_appended(b"From a\n", &metadata));
+    }
+
+    #[test]
+    fn test_load_stale_index() {
+        let dir = tmp_dir(&[("a.mbox", "From a\n\nbody\n")]);
+        let path = dir.path().join("a.mbox").to_string_lossy().to_string();
+        let (data, metadata) = read(&path);
+        let index = MboxIndex { format: MboxFormat::Mboxo, detected: true, mode: ParseMode::Strict, lex_stats: LexStats::default(), report: ParseReport::default(), emails: vec![1usize] };
+        save(&path, &data, &metadata, &index).unwrap();
+
+        fs::write(&path, b"From a\n\nbodY\n").unwrap();
+        let (data, _) = read(&path);
+        assert!(load::<usize>(&path, &data, &metadata).is_none());
+        fs::write(&path, b"From a\n\nbody\nFrom b\n").unwrap();
+        let (data, metadata) = read(&path);
+        assert!(load::<usize>(&path, &data, &metadata).is_none());
+    }
+
+    #[test]
+    fn test_load_missing_or_invalid_index() {
+        let dir = tmp_dir(&[("a.mbox", "From a\n\nbody\n")]);
+        let path = dir.path().join("a.mbox").to_string_lossy().to_string();
+        let (data, metadata) = read(&path);
+        assert!(load::<usize>(&path, &data, &metadata).is_none());
+        fs::write(index_path(&path), b"MBOXIDX\0\x63\0\0\0garbage").unwrap();
+        assert!(load::<usize>(&path, &data, &metadata).is_none());
+        // valid header and validation then an emails list of 2^60 elements
+        let mut index = b"MBOXIDX\0".to_vec();
+        index.extend(INDEX_VERSION.to_le_bytes());
+        index.extend([0; 36 + 4 + 1 + 4 + 16 + 8]);
+        index.extend((1u64 << 60).to_le_bytes());
+        fs::write(index_path(&path), index).unwrap();
+        assert!(load::<usize>(&path, &data, &metadata).is_none());
+    }
+}
@@ -0,0 +1,185 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{format::{content_length, is_message_end, MboxFormat}, header::{next_line, HeaderBlock}};
+
+/// Messages and bytes consumed by a lexer. Bytes before the first `From ` line are not consumed.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct LexStats {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+/// Iterator over the messages of a mailbox, scanning the mapped bytes for `From ` separator lines.
+/// Lines are not required to be valid UTF-8 and may end with LF, CRLF or nothing at the end of the file.
+/// Only the current position is kept, so memory use does not depend on the file size.
+pub struct MboxLexer<'a> {
+    data: &'a [u8],
+    format: MboxFormat,
+    pos: usize,
+    stats: LexStats,
+}
+
+impl<'a> MboxLexer<'a> {
+
+    pub fn new(data: &'a [u8], format: MboxFormat) -> Self {
+        MboxLexer { data, format, pos: 0, stats: LexStats::default() }
+    }
+
+    /// Lexer over the messages starting in `chunk`, a range returned by `split_chunks`.
+    /// Ranges produced are positions in `data`.
+    pub fn with_range(data: &'a [u8], format: MboxFormat, chunk: Range<usize>) -> Self {
+        MboxLexer { data: &data[..chunk.end], format, pos: chunk.start, stats: LexStats::default() }
+    }
+
+    /// Split `data` in at most `nb_chunks` ranges of similar size, each one starting on a `From ` line
+    /// (except the first one), so that they can be lexed independently.
+    /// Not suitable for `Content-Length` formats, where a message can contain unquoted `From ` lines.
+    pub fn split_chunks(data: &[u8], nb_chunks: usize) -> Vec<Range<usize>> {
+        let mut chunks = vec![];
+        let mut start = 0;
+        for idx in 1..nb_chunks {
+            let target = data.len() * idx / nb_chunks;
+            if target <= start {
+                continue;
+            }
+            let line_start = data[target..].iter().position(|&c| c == b'\n').map_or(data.len(), |pos| target + pos + 1);
+            let separator = find_separator(data, line_start).unwrap_or(data.len());
+            if separator > start && separator < data.len() {
+                chunks.push(Range { start, end: separator });
+                start = separator;
+            }
+        }
+        chunks.push(Range { start, end: data.len() });
+        chunks
+    }
+
+    pub fn stats(&self) -> LexStats {
+        self.stats
+    }
+
+    fn find_separator(&self, from: usize) -> Option<usize> {
+        find_separator(self.data, from)
+    }
+
+    /// End of the message body announced by its `Content-Length`, if it ends on a message boundary.
+    /// A length overflowing the end position is invalid.
+    fn content_length_end(&self, email: &Range<usize>) -> Option<usize> {
+        let block = HeaderBlock::parse(&self.data[email.start..email.end], email.start);
+        content_length(self.data, &block)
+            .and_then(|len| block.body_start.checked_add(len))
+            .filter(|end| is_message_end(self.data, *end))
+    }
+
+}
+
+/// Position of the first line starting with `From ` at or after `from`, which must be a line start.
+fn find_separator(data: &[u8], from: usize) -> Option<usize> {
+    let mut pos = from;
+    loop {
+        if data[pos..].starts_with(b"From ") {
+            return Some(pos);
+        }
+        pos += data[pos..].iter().position(|&c| c == b'\n')? + 1;
+    }
+}
+
+impl Iterator for MboxLexer<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.find_separator(self.pos)?;
+        let (_, next) = next_line(self.data, start);
+        let mut end = self.find_separator(next).unwrap_or(self.data.len());
+        if self.format.use_content_length() && let Some(body_end) = self.content_length_end(&Range { start, end }) {
+            // `From ` lines inside the announced length are part of the body
+            while end < body_end && let Some(next_start) = self.find_separator(next_line(self.data, end).1) {
+                end = next_start;
+            }
+            if end < body_end {
+                end = self.data.len();
+            }
+        }
+        self.pos = end;
+        self.stats.messages += 1;
+        self.stats.bytes += end - start;
+        Some(Range { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lex_separators() {
+        let data = b"garbage\nFrom a\nbody\n>From quoted\nFrom b\nFrom: b@b.c\n\nbody";
+        let ranges: Vec<_> = MboxLexer::new(data, MboxFormat::Mboxo).collect();
+        assert_eq!(vec![8..33, 33..data.len()], ranges);
+    }
+
+    #[test]
+    fn test_lex_seek_positions() {
+        let data = std::fs::read("datasets/test_seek_positions.mbox").unwrap();
+        let ranges: Vec<_> = MboxLexer::new(&data, MboxFormat::Mboxo).collect();
+        assert_eq!(3, ranges.len());
+        assert_eq!(25, ranges[1].start);
+        assert_eq!(data.len(), ranges[2].end);
+    }
+
+    #[test]
+    fn test_lex_content_length() {
+        let data = b"From a\nContent-Length: 25\n\nFrom inside\nFrom inside\n\nFrom c\n\nbody\n";
+        let ranges: Vec<_> = MboxLexer::new(data, MboxFormat::Mboxcl2).collect();
+        assert_eq!(vec![0..52, 52..data.len()], ranges);
+        let ranges: Vec<_> = MboxLexer::new(data, MboxFormat::Mboxo).collect();
+        assert_eq!(4, ranges.len());
+    }
+
+    #[test]
+    fn test_lex_content_length_overflow() {
+        let data = b"From a\nContent-Length: 18446744073709551615\n\nbody\n\nFrom c\n\nbody\n";
+        let ranges: Vec<_> = MboxLexer::new(data, MboxFormat::Mboxcl2).collect();
+        assert_eq!(vec![0..51, 51..data.len()], ranges);
+    }
+
+    #[test]
+    fn test_lex_crlf_non_utf8() {
+        let data = b"From a\r\nSubject: caf\xe9\r\n\r\nbody\r\nFrom b\r\n\r\nno final line ending";
+        let mut lexer = MboxLexer::new(data, MboxFormat::Mboxo);
+        let ranges: Vec<_> = lexer.by_ref().collect();
+        assert_eq!(vec![0..31, 31..data.len()], ranges);
+        assert_eq!(LexStats { messages: 2, bytes: data.len() }, lexer.stats());
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let data = std::fs::read("datasets/test_emails.mbox").unwrap();
+        let chunks = MboxLexer::split_chunks(&data, 7);
+        assert_eq!(7, chunks.len());
+        assert_eq!(0, chunks[0].start);
+        assert_eq!(data.len(), chunks[6].end);
+        for window in chunks.windows(2) {
+            assert_eq!(window[0].end, window[1].start);
+            assert!(data[window[1].start..].starts_with(b"From "));
+        }
+        let sequential: Vec<_> = MboxLexer::new(&data, MboxFormat::Mboxo).collect();
+        let chunked: Vec<_> = chunks.into_iter()
+            .flat_map(|chunk| MboxLexer::with_range(&data, MboxFormat::Mboxo, chunk).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(sequential, chunked);
+    }
+
+    #[test]
+    fn test_split_chunks_small_data() {
+        assert_eq!(vec![0..0], MboxLexer::split_chunks(b"", 4));
+        assert_eq!(vec![0..9], MboxLexer::split_chunks(b"From a\nb\n", 4));
+    }
+
+    #[test]
+    fn test_lex_empty() {
+        assert_eq!(0, MboxLexer::new(b"", MboxFormat::Mboxo).count());
+        assert_eq!(0, MboxLexer::new(b"no separator\n", MboxFormat::Mboxo).count());
+    }
+}
@@ -0,0 +1,239 @@
+use std::{borrow::Cow, collections::BTreeMap, fs, path::{Path, PathBuf}, time::SystemTime};
+
+use tracing::{debug, instrument, warn};
+
+use crate::{mailbox::Attachment, storage::{message::{EmailFilePtr, MessageFile}, view::EmailView, MailboxError}, Email, MailStorageRepository};
+
+/// Sub-directories holding delivered messages, `tmp` only holds messages being delivered.
+const MAILDIR_SUBDIRS: [&str; 2] = ["new", "cur"];
+
+/// Standard flags of the `:2,` info suffix of maildir file names.
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display)]
+pub enum MaildirFlag {
+    Passed,
+    Replied,
+    Seen,
+    Trashed,
+    Draft,
+    Flagged,
+}
+
+impl MaildirFlag {
+
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            'P' => Some(MaildirFlag::Passed),
+            'R' => Some(MaildirFlag::Replied),
+            'S' => Some(MaildirFlag::Seen),
+            'T' => Some(MaildirFlag::Trashed),
+            'D' => Some(MaildirFlag::Draft),
+            'F' => Some(MaildirFlag::Flagged),
+            _ => None,
+        }
+    }
+
+}
+
+#[derive(Debug)]
+struct MaildirMessage {
+    path: PathBuf,
+    /// Delivered in `new` and not yet seen by a mail client.
+    is_new: bool,
+    flags: Vec<MaildirFlag>,
+    /// Modification time of the file when it was parsed, kept when a client renames it.
+    modified: Option<SystemTime>,
+    email: EmailFilePtr,
+}
+
+/// Maildir mailbox, one file per message. Emails are identified by the unique part of their file name,
+/// which does not change when a client moves a message from `new` to `cur` or updates its flags.
+#[derive(Debug)]
+pub struct Maildir {
+    path: PathBuf,
+    messages: BTreeMap<String, MaildirMessage>,
+}
+
+impl Maildir {
+
+    #[instrument]
+    pub fn new(path: &str) -> Result<Self, MailboxError> {
+        let path = PathBuf::from(path);
+        if !is_maildir(&path) {
+            return Err(MailboxError::MaildirNotFound);
+        }
+        let mut messages = BTreeMap::new();
+        for subdir in MAILDIR_SUBDIRS {
+            for entry in fs::read_dir(path.join(subdir))? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.starts_with('.') || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let (id, flags) = parse_file_name(&file_name);
+                let modified = entry.metadata()?.modified().ok();
+                let data = fs::read(entry.path())?;
+                match EmailFilePtr::parse(&data, 0..data.len()) {
+                    Ok(email) => {
+                        messages.insert(id.to_string(), MaildirMessage { path: entry.path(), is_new: subdir == "new", flags, modified, email });
+                    }
+                    Err(_) => warn!("Skip invalid maildir message {file_name}"),
+                }
+            }
+        }
+        debug!("Loaded {} emails from maildir", messages.len());
+        Ok(Maildir { path, messages })
+    }
+
+    /// Flags of the email `id` when the maildir was read.
+    pub fn flags(&self, id: &str) -> Result<&[MaildirFlag], MailboxError> {
+        self.messages.get(id).map(|message| message.flags.as_slice()).ok_or(MailboxError::EmailNotFound)
+    }
+
+    /// Whether the email `id` was in `new`, not yet seen by a mail client.
+    pub fn is_new(&self, id: &str) -> Result<bool, MailboxError> {
+        self.messages.get(id).map(|message| message.is_new).ok_or(MailboxError::EmailNotFound)
+    }
+
+    /// Message `id` with the content of its file, looked up again by its unique name when a client has
+    /// renamed or moved it. Fails when the file was changed since it was parsed.
+    fn read(&self, id: &str) -> Result<MessageFile<'_>, MailboxError> {
+        let message = self.messages.get(id).ok_or(MailboxError::EmailNotFound)?;
+        match MessageFile::read(&message.path, &message.email, message.modified) {
+            Err(MailboxError::EmailNotFound) => {
+                let path = MAILDIR_SUBDIRS.iter()
+                    .filter_map(|subdir| fs::read_dir(self.path.join(subdir)).ok())
+                    .flatten()
+                    .filter_map(|entry| entry.ok())
+                    .find(|entry| parse_file_name(&entry.file_name().to_string_lossy()).0 == id)
+                    .ok_or(MailboxError::EmailNotFound)?
+                    .path();
+                MessageFile::read(&path, &message.email, message.modified)
+            }
+            res => res,
+        }
+    }
+
+}
+
+/// Unique name and flags of a maildir file name `unique:2,flags`.
+fn parse_file_name(file_name: &str) -> (&str, Vec<MaildirFlag>) {
+    match file_name.split_once(':') {
+        Some((unique, info)) => {
+            let flags = info.strip_prefix("2,").unwrap_or_default().chars().filter_map(MaildirFlag::from_char).collect();
+            (unique, flags)
+        }
+        None => (file_name, vec![]),
+    }
+}
+
+impl MailStorageRepository for Maildir {
+    type EmailId = String;
+
+    fn get_email(&self, id: &Self::EmailId) -> Result<Email<Self::EmailId>, MailboxError> {
+        self.read(id)?.email(id.clone())
+    }
+
+    fn count_emails(&self) -> Result<usize, MailboxError> {
+        Ok(self.messages.len())
+    }
+
+    fn emails(&self) -> impl Iterator<Item = Email<Self::EmailId>> {
+        self.messages.keys().filter_map(|id| self.get_email(id).ok())
+    }
+
+    fn email_view(&self, id: &Self::EmailId) -> Result<EmailView<'_, Self::EmailId>, MailboxError> {
+        Ok(self.read(id)?.view(id.clone()))
+    }
+
+    fn email_views(&self) -> impl Iterator<Item = EmailView<'_, Self::EmailId>> {
+        self.messages.keys().filter_map(|id| self.email_view(id).ok())
+    }
+
+    fn header(&self, id: &Self::EmailId, name: &str) -> Result<Option<String>, MailboxError> {
+        self.read(id)?.header(name)
+    }
+
+    fn raw_email(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        Ok(self.read(id)?.raw_email())
+    }
+
+    fn raw_headers(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError> {
+        Ok(self.read(id)?.raw_headers())
+    }
+
+    fn raw_part(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        self.read(id)?.raw_part(index)
+    }
+
+    fn attachments(&self, id: &Self::EmailId) -> Result<Vec<Attachment>, MailboxError> {
+        Ok(self.read(id)?.attachments())
+    }
+
+    fn attachment_content(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError> {
+        self.read(id)?.attachment_content(index)
+    }
+
+}
+
+/// Whether `path` looks like a maildir, with `cur` and `new` sub-directories.
+pub fn is_maildir(path: &Path) -> bool {
+    MAILDIR_SUBDIRS.iter().all(|subdir| path.join(subdir).is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::tmp_dir;
+
+    use super::*;
+
+    const MESSAGE: &str = "From: Jean <jean@example.fr>\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: Bonjour\n\nCorps\n";
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(("1754279778.M1P2.host", vec![MaildirFlag::Replied, MaildirFlag::Seen]), parse_file_name("1754279778.M1P2.host:2,RS"));
+        assert_eq!(("1754279778.M1P2.host", vec![]), parse_file_name("1754279778.M1P2.host"));
+        assert_eq!(("1754279778.M1P2.host", vec![MaildirFlag::Flagged]), parse_file_name("1754279778.M1P2.host:2,Fa"));
+        assert_eq!(("1754279778.M1P2.host", vec![]), parse_file_name("1754279778.M1P2.host:1,experimental"));
+    }
+
+    #[test]
+    fn test_read_maildir() {
+        let dir = tmp_dir(&[
+            ("cur/2.host:2,S", MESSAGE),
+            ("new/1.host", &MESSAGE.replace("Bonjour", "Nouveau")),
+            ("cur/3.host:2,", "Subject: invalid\n\nno date\n"),
+            ("tmp/4.host", MESSAGE),
+        ]);
+        let path = dir.path();
+        let maildir = Maildir::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(2, maildir.count_emails().unwrap());
+        assert_eq!(vec!["1.host", "2.host"], maildir.emails().map(|email| email.id).collect::<Vec<_>>());
+        let email = maildir.get_email(&"2.host".to_string()).unwrap();
+        assert_eq!(Some("Bonjour"), email.subject.as_deref());
+        assert_eq!(Some("Corps\n".to_string()), email.body_text);
+        let view = maildir.email_view(&"2.host".to_string()).unwrap();
+        assert_eq!((Some("Bonjour"), Some("Corps\n")), (view.subject(), view.body_text()));
+        assert_eq!(vec!["1.host", "2.host"], maildir.email_views().map(EmailView::into_id).collect::<Vec<_>>());
+        let id = "2.host".to_string();
+        assert_eq!(MESSAGE.as_bytes(), maildir.raw_email(&id).unwrap().as_ref());
+        assert_eq!(b"From: Jean <jean@example.fr>\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: Bonjour\n".as_slice(), maildir.raw_headers(&id).unwrap().as_ref());
+        assert_eq!(MESSAGE.as_bytes(), maildir.raw_part(&id, 0).unwrap().as_ref());
+        assert!(maildir.raw_part(&id, 1).is_err());
+        assert_eq!(&[MaildirFlag::Seen], maildir.flags("2.host").unwrap());
+        assert!(maildir.is_new("1.host").unwrap());
+        assert!(maildir.get_email(&"3.host".to_string()).is_err_and(|e| e == MailboxError::EmailNotFound));
+
+        // a client marks the message as replied
+        fs::rename(path.join("cur/2.host:2,S"), path.join("cur/2.host:2,RS")).unwrap();
+        assert_eq!(Some("Bonjour"), maildir.get_email(&"2.host".to_string()).unwrap().subject.as_deref());
+        // then truncates it
+        fs::write(path.join("cur/2.host:2,RS"), &MESSAGE[..20]).unwrap();
+        assert!(maildir.get_email(&"2.host".to_string()).is_err_and(|e| e == MailboxError::EmailFileChanged));
+    }
+
+    #[test]
+    fn test_not_a_maildir() {
+        assert!(Maildir::new("datasets").is_err_and(|e| e == MailboxError::MaildirNotFound));
+        assert!(!is_maildir(Path::new("datasets/test_lex.mbox")));
+    }
+}
@@ -0,0 +1,463 @@
+use std::{borrow::Cow, fs::File, io::Read, ops::Range, path::Path, time::SystemTime};
+
+use chrono::{DateTime, FixedOffset};
+use rfc2047_decoder::{Decoder, RecoverStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::{mailbox::{Address, Attachment}, storage::{address::parse_address_list, date, encoding::{decode_charset, TransferEncoding}, format::MboxFormat, header::{next_line, unfold}, mime::MimePart, report::{ParseIssue, ParseIssueKind, ParseReason}, view::EmailView, MailboxError}, Email};
+
+pub type SeekRange = (u64, u64);
+
+/// How messages missing headers are handled.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, strum::Display, strum::EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum ParseMode {
+    /// Messages without `Subject`, `From` or a valid `Date` are skipped.
+    #[default]
+    Strict,
+    /// Messages without `Subject` are kept, and without a valid `Date` they are dated by their `From `
+    /// separator line or their newest `Received` header. Only messages without `From` or any date are skipped.
+    Lenient,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct BodyFilePtr {
+    pub(crate) content_type: String,
+    pub(crate) charset: Option<String>,
+    pub(crate) content_transfer_encoding: String,
+    pub(crate) content: Range<usize>,
+}
+
+impl BodyFilePtr {
+
+    fn is_html(&self) -> bool {
+        self.content_type.contains("text/html")
+    }
+
+}
+
+impl From<&MimePart> for BodyFilePtr {
+    fn from(part: &MimePart) -> Self {
+        BodyFilePtr {
+            content_type: part.content_type.clone(),
+            charset: part.charset.clone(),
+            content_transfer_encoding: part.content_transfer_encoding.clone(),
+            content: part.body.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct EmailFilePtr {
+    pub(crate) email: Range<usize>,
+    pub(crate) subject: Option<Range<usize>>,
+    pub(crate) from: Range<usize>,
+    /// Date with the offset written by the sender.
+    pub(crate) datetime: DateTime<FixedOffset>,
+    pub(crate) to: Option<Range<usize>>,
+    pub(crate) cc: Option<Range<usize>>,
+    pub(crate) bcc: Option<Range<usize>>,
+    pub(crate) reply_to: Option<Range<usize>>,
+    pub(crate) message_id: Option<Range<usize>>,
+    pub(crate) in_reply_to: Option<Range<usize>>,
+    pub(crate) references: Option<Range<usize>>,
+    pub(crate) list_id: Option<Range<usize>>,
+    pub(crate) bodies: Vec<BodyFilePtr>,
+    pub(crate) mime: MimePart,
+}
+
+impl EmailFilePtr {
+
+    /// Parse the message at `email` in `data`, failing when a required header is missing or invalid.
+    pub(crate) fn parse(data: &[u8], email: Range<usize>) -> Result<Self, MailboxError> {
+        Self::parse_reporting(data, email, ParseMode::Strict, &mut vec![]).ok_or(MailboxError::MboxValidationError)
+    }
+
+    /// Parse the message at `email` in `data`, adding to `issues` why it is skipped or how it was repaired.
+    pub(crate) fn parse_reporting(data: &[u8], email: Range<usize>, mode: ParseMode, issues: &mut Vec<ParseIssue>) -> Option<Self> {
+        let mut validator = EmailFilePtrValidator::new(mode);
+        validator.email = Some((email.start as u64, email.end as u64));
+        validator.set_content(data, email);
+        validator.validate(issues)
+    }
+
+}
+
+struct EmailFilePtrValidator {
+    mode: ParseMode,
+    email: Option<SeekRange>,
+    subject: Option<SeekRange>,
+    from: Option<SeekRange>,
+    date: Option<SeekRange>,
+    datetime: Option<DateTime<FixedOffset>>,
+    /// Date of the separator line or `Received` headers, only looked for in lenient mode.
+    fallback_datetime: Option<DateTime<FixedOffset>>,
+    to: Option<SeekRange>,
+    cc: Option<SeekRange>,
+    bcc: Option<SeekRange>,
+    reply_to: Option<SeekRange>,
+    message_id: Option<SeekRange>,
+    in_reply_to: Option<SeekRange>,
+    references: Option<SeekRange>,
+    list_id: Option<SeekRange>,
+    mime: Option<MimePart>,
+}
+
+impl EmailFilePtrValidator {
+    fn new(mode: ParseMode) -> Self {
+        Self { mode, email: None, subject: None, from: None, date: None, datetime: None, fallback_datetime: None, to: None, cc: None, bcc: None, reply_to: None,
+            message_id: None, in_reply_to: None, references: None, list_id: None, mime: None }
+    }
+
+    fn set_content(&mut self, data: &[u8], email: Range<usize>) {
+        let mime = MimePart::parse(data, email.clone());
+        let find = |name: &str| mime.headers.iter().find(|header| header.is(data, name));
+        let seek_range = |name: &str| find(name).map(|h| (h.value.start as u64, h.value.end as u64));
+        self.subject = seek_range("Subject");
+        self.from = seek_range("From");
+        self.to = seek_range("To");
+        self.cc = seek_range("Cc");
+        self.bcc = seek_range("Bcc");
+        self.reply_to = seek_range("Reply-To");
+        self.message_id = seek_range("Message-ID");
+        self.in_reply_to = seek_range("In-Reply-To");
+        self.references = seek_range("References");
+        self.list_id = seek_range("List-Id");
+        self.date = seek_range("Date");
+        self.datetime = find("Date")
+            .and_then(|h| date::parse_date(&String::from_utf8_lossy(&h.unfolded_value(data))));
+        if self.datetime.is_none() && self.mode == ParseMode::Lenient {
+            self.fallback_datetime = separator_datetime(data, &email).or_else(|| received_datetime(data, &mime));
+        }
+        self.mime = Some(mime);
+    }
+
+    fn validate(self, issues: &mut Vec<ParseIssue>) -> Option<EmailFilePtr> {
+        let offset = self.email.map_or(0, |email| email.0 as usize);
+        let lenient = self.mode == ParseMode::Lenient;
+        let datetime = self.datetime.or(self.fallback_datetime);
+        let mut message_issues = vec![];
+        if self.subject.is_none() {
+            let kind = if lenient { ParseIssueKind::Repaired } else { ParseIssueKind::Skipped };
+            message_issues.push(ParseIssue::new(offset, kind, ParseReason::MissingField, Some("Subject")));
+        }
+        if self.from.is_none() {
+            message_issues.push(ParseIssue::new(offset, ParseIssueKind::Skipped, ParseReason::MissingField, Some("From")));
+        }
+        if self.datetime.is_none() {
+            let kind = if datetime.is_some() { ParseIssueKind::Repaired } else { ParseIssueKind::Skipped };
+            let reason = if self.date.is_some() { ParseReason::InvalidField } else { ParseReason::MissingField };
+            message_issues.push(ParseIssue::new(offset, kind, reason, Some("Date")));
+        }
+        if let Some(mime) = &self.mime && mime.missing_close_delimiter() {
+            message_issues.push(ParseIssue::new(offset, ParseIssueKind::Repaired, ParseReason::MissingCloseDelimiter, None));
+        }
+        let email_ptr = match (self.email, self.from, datetime, self.mime) {
+            (Some(email), Some(from), Some(datetime), Some(mime)) if self.subject.is_some() || lenient => Some(EmailFilePtr{
+                email: Range { start: email.0 as usize, end: email.1 as usize },
+                subject: self.subject.map(to_range),
+                from: Range { start: from.0 as usize, end: from.1 as usize },
+                datetime,
+                to: self.to.map(to_range),
+                cc: self.cc.map(to_range),
+                bcc: self.bcc.map(to_range),
+                reply_to: self.reply_to.map(to_range),
+                message_id: self.message_id.map(to_range),
+                in_reply_to: self.in_reply_to.map(to_range),
+                references: self.references.map(to_range),
+                list_id: self.list_id.map(to_range),
+                bodies: mime.text_parts().into_iter().map(BodyFilePtr::from).collect(),
+                mime,
+            }),
+            _ => None,
+        };
+        if email_ptr.is_none() {
+            // repairs of a skipped message did not happen
+            message_issues.retain(|issue| issue.kind == ParseIssueKind::Skipped);
+        }
+        issues.extend(message_issues);
+        email_ptr
+    }
+}
+
+/// Date of the mbox `From ` separator line of the message, `From sender Mon Aug  4 11:56:07 2025`,
+/// read as the `Date` header is : in UTC unless the line gives a zone.
+fn separator_datetime(data: &[u8], email: &Range<usize>) -> Option<DateTime<FixedOffset>> {
+    if !data[email.start..email.end].starts_with(b"From ") {
+        return None;
+    }
+    let (line, _) = next_line(data, email.start);
+    let line = String::from_utf8_lossy(&data[line]);
+    let date = line.split_whitespace().skip(2).collect::<Vec<_>>().join(" ");
+    date::parse_date(&date)
+}
+
+/// Newest date of the `Received` headers, written after their last `;`.
+fn received_datetime(data: &[u8], mime: &MimePart) -> Option<DateTime<FixedOffset>> {
+    mime.headers.iter()
+        .filter(|header| header.is(data, "Received"))
+        .filter_map(|header| {
+            let value = String::from_utf8_lossy(&header.unfolded_value(data)).to_string();
+            value.rsplit_once(';').and_then(|(_, date)| date::parse_date(date))
+        })
+        .max()
+}
+
+/// Content without its final empty line, which ends a header section or belongs to the mbox separator.
+fn strip_empty_line(content: &[u8]) -> &[u8] {
+    if content.ends_with(b"\r\n\r\n") {
+        &content[..content.len() - 2]
+    } else if content.ends_with(b"\n\n") {
+        &content[..content.len() - 1]
+    } else {
+        content
+    }
+}
+
+fn to_range(seek_range: SeekRange) -> Range<usize> {
+    Range { start: seek_range.0 as usize, end: seek_range.1 as usize }
+}
+
+/// Message alone in a file read on access, for storages with one file per message. Values borrowing
+/// the file content are returned owned.
+pub(crate) struct MessageFile<'a> {
+    email_ptr: &'a EmailFilePtr,
+    data: Vec<u8>,
+}
+
+impl<'a> MessageFile<'a> {
+
+    /// Read the file at `path`, parsed as `email_ptr` when it was last modified at `modified`. Fails when
+    /// the file was changed since, its content no longer matching the ranges of `email_ptr`.
+    pub(crate) fn read(path: &Path, email_ptr: &'a EmailFilePtr, modified: Option<SystemTime>) -> Result<Self, MailboxError> {
+        let mut file = File::open(path).or(Err(MailboxError::EmailNotFound))?;
+        let metadata = file.metadata().or(Err(MailboxError::EmailNotFound))?;
+        if metadata.modified().ok() != modified || metadata.len() != email_ptr.email.end as u64 {
+            return Err(MailboxError::EmailFileChanged);
+        }
+        let mut data = Vec::with_capacity(email_ptr.email.end);
+        file.read_to_end(&mut data).or(Err(MailboxError::EmailNotFound))?;
+        if data.len() != email_ptr.email.end {
+            return Err(MailboxError::EmailFileChanged);
+        }
+        Ok(MessageFile { email_ptr, data })
+    }
+
+    pub(crate) fn email<EmailId>(&self, id: EmailId) -> Result<Email<EmailId>, MailboxError> {
+        self.reader().email(id, self.email_ptr)
+    }
+
+    pub(crate) fn view<EmailId>(self, id: EmailId) -> EmailView<'a, EmailId> {
+        EmailView::new(id, Cow::Owned(self.data), None, self.email_ptr)
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Result<Option<String>, MailboxError> {
+        self.reader().header(self.email_ptr, name)
+    }
+
+    pub(crate) fn raw_email<'b>(self) -> Cow<'b, [u8]> {
+        Cow::Owned(self.data)
+    }
+
+    pub(crate) fn raw_headers<'b>(&self) -> Cow<'b, [u8]> {
+        Cow::Owned(self.reader().raw_headers(self.email_ptr).into_owned())
+    }
+
+    pub(crate) fn raw_part<'b>(&self, index: usize) -> Result<Cow<'b, [u8]>, MailboxError> {
+        self.reader().raw_part(self.email_ptr, index).map(|part| Cow::Owned(part.into_owned()))
+    }
+
+    pub(crate) fn attachments(&self) -> Vec<Attachment> {
+        self.reader().attachments(self.email_ptr)
+    }
+
+    pub(crate) fn attachment_content<'b>(&self, index: usize) -> Result<Cow<'b, [u8]>, MailboxError> {
+        self.reader().attachment_content(self.email_ptr, index).map(|content| Cow::Owned(content.into_owned()))
+    }
+
+    fn reader(&self) -> MessageReader<'_> {
+        MessageReader::new(&self.data, None)
+    }
+
+}
+
+/// Decoding of parsed messages into `Email`, `data` being the bytes their ranges point into.
+pub(crate) struct MessageReader<'a> {
+    data: &'a [u8],
+    /// Mbox variant whose `From ` quoting is reversed in bodies, `None` for storages without quoting.
+    format: Option<MboxFormat>,
+}
+
+impl<'a> MessageReader<'a> {
+
+    pub(crate) fn new(data: &'a [u8], format: Option<MboxFormat>) -> Self {
+        MessageReader { data, format }
+    }
+
+    pub(crate) fn email<EmailId>(&self, id: EmailId, email_ptr: &EmailFilePtr) -> Result<Email<EmailId>, MailboxError> {
+        let body_text = self.body_text(email_ptr);
+        let body_html = self.body_html(email_ptr);
+        let body_error = body_text.as_ref().err().or(body_html.as_ref().err()).copied();
+        let (body_text, body_html) = (body_text.ok().flatten(), body_html.ok().flatten());
+        Ok(Email {
+            id,
+            from: self.get_header(&email_ptr.from)?,
+            from_address: self.sender_address(email_ptr),
+            datetime: email_ptr.datetime.to_utc(),
+            sender_datetime: email_ptr.datetime,
+            subject: self.get_optional_header(&email_ptr.subject)?,
+            to: email_ptr.to.as_ref().map(|range| self.get_addresses(range)).unwrap_or_default(),
+            cc: email_ptr.cc.as_ref().map(|range| self.get_addresses(range)).unwrap_or_default(),
+            bcc: email_ptr.bcc.as_ref().map(|range| self.get_addresses(range)).unwrap_or_default(),
+            reply_to: email_ptr.reply_to.as_ref().map(|range| self.get_addresses(range)).unwrap_or_default(),
+            message_id: email_ptr.message_id.as_ref().map(|range| self.get_header_lenient(range)),
+            in_reply_to: email_ptr.in_reply_to.as_ref().map(|range| self.get_header_lenient(range)),
+            references: email_ptr.references.as_ref().map(|range| self.get_header_lenient(range)),
+            list_id: email_ptr.list_id.as_ref().map(|range| self.get_header_lenient(range)),
+            text_repaired: body_text.as_ref().is_some_and(|(_, repaired)| *repaired)
+                        || body_html.as_ref().is_some_and(|(_, repaired)| *repaired),
+            body_text: body_text.map(|(text, _)| text.into_owned()),
+            body_html: body_html.map(|(html, _)| html.into_owned()),
+            body_error,
+            attachments: self.attachments(email_ptr),
+        })
+    }
+
+    /// First mailbox of the `From` header.
+    pub(crate) fn sender_address(&self, email_ptr: &EmailFilePtr) -> Option<Address> {
+        self.get_addresses(&email_ptr.from).into_iter().next()
+    }
+
+    /// First text body, decoded and converted to UTF-8, with a flag set when the text had to be repaired.
+    pub(crate) fn body_text(&self, email_ptr: &EmailFilePtr) -> Result<Option<(Cow<'a, str>, bool)>, MailboxError> {
+        email_ptr.bodies.iter()
+            .find(|bp| !bp.is_html())
+            .map(|bp| self.get_body(bp))
+            .transpose()
+    }
+
+    /// First HTML body, as in `body_text`.
+    pub(crate) fn body_html(&self, email_ptr: &EmailFilePtr) -> Result<Option<(Cow<'a, str>, bool)>, MailboxError> {
+        email_ptr.bodies.iter()
+            .find(|bp| bp.is_html())
+            .map(|bp| self.get_body(bp))
+            .transpose()
+    }
+
+    /// Decoded value of the header at `range`, borrowed when it is plain ASCII on one line without encoded words.
+    pub(crate) fn header_value(&self, range: &Range<usize>) -> Result<Cow<'a, str>, MailboxError> {
+        let value = &self.data[range.start..range.end];
+        if value.iter().all(|&c| c.is_ascii() && c != b'\r' && c != b'\n') && !value.windows(2).any(|window| window == b"=?")
+                && let Ok(value) = str::from_utf8(value) {
+            return Ok(Cow::Borrowed(value));
+        }
+        self.get_header(range).map(Cow::Owned)
+    }
+
+    pub(crate) fn header(&self, email_ptr: &EmailFilePtr, name: &str) -> Result<Option<String>, MailboxError> {
+        email_ptr.mime.headers.iter()
+            .find(|header| header.is(self.data, name))
+            .map(|header| self.get_header(&header.value))
+            .transpose()
+    }
+
+    /// Original bytes of the message, without the mbox `From ` line, the blank line separating it
+    /// from the next message and the mboxrd quoting.
+    pub(crate) fn raw_email(&self, email_ptr: &EmailFilePtr) -> Cow<'a, [u8]> {
+        let content = &self.data[self.content_start(email_ptr)..email_ptr.email.end];
+        if self.format.is_some() {
+            self.unescape(strip_empty_line(content))
+        } else {
+            Cow::Borrowed(content)
+        }
+    }
+
+    /// Header section of the message, without the empty line ending it.
+    pub(crate) fn raw_headers(&self, email_ptr: &EmailFilePtr) -> Cow<'a, [u8]> {
+        let start = self.content_start(email_ptr);
+        self.unescape(strip_empty_line(&self.data[start..email_ptr.mime.body.start.max(start)]))
+    }
+
+    /// Part at `index` of the MIME tree in depth-first order, 0 being the whole message, with its headers
+    /// and its content not decoded.
+    pub(crate) fn raw_part(&self, email_ptr: &EmailFilePtr, index: usize) -> Result<Cow<'a, [u8]>, MailboxError> {
+        if index == 0 {
+            return Ok(self.raw_email(email_ptr));
+        }
+        let part = email_ptr.mime.parts().into_iter().nth(index).ok_or(MailboxError::PartNotFound)?;
+        Ok(self.unescape(&self.data[part.part.start..part.part.end]))
+    }
+
+    pub(crate) fn attachments(&self, email_ptr: &EmailFilePtr) -> Vec<Attachment> {
+        email_ptr.mime.attachment_parts().into_iter()
+            .map(|part| part.attachment(self.data))
+            .collect()
+    }
+
+    pub(crate) fn attachment_content(&self, email_ptr: &EmailFilePtr, index: usize) -> Result<Cow<'a, [u8]>, MailboxError> {
+        let part = email_ptr.mime.attachment_parts().into_iter().nth(index)
+            .ok_or(MailboxError::AttachmentNotFound)?;
+        self.decode_content(&part.body, &part.content_transfer_encoding)
+    }
+
+    fn get_header(&self, range: &Range<usize>) -> Result<String, MailboxError> {
+        let decoder = Decoder::new().too_long_encoded_word_strategy(RecoverStrategy::Skip);
+        // raw 8-bit headers are not valid RFC 5322 but common, read them like an undeclared charset body
+        let unfolded = unfold(&self.data[range.start..range.end]);
+        let (value, _) = decode_charset(&unfolded, None);
+        decoder.decode(value.as_bytes())
+            .or(Err(MailboxError::EncodedWordDecodeError))
+    }
+
+    /// Addresses of an address list header, parsed before decoding encoded words so that they cannot
+    /// introduce separators.
+    fn get_addresses(&self, range: &Range<usize>) -> Vec<Address> {
+        let unfolded = unfold(&self.data[range.start..range.end]);
+        parse_address_list(&decode_charset(&unfolded, None).0)
+    }
+
+    /// Header value with its encoded words decoded, or unfolded as written when one of them is malformed.
+    fn get_header_lenient(&self, range: &Range<usize>) -> String {
+        self.get_header(range).unwrap_or_else(|_| decode_charset(&unfold(&self.data[range.start..range.end]), None).0.into_owned())
+    }
+
+    fn get_optional_header(&self, range: &Option<Range<usize>>) -> Result<Option<String>, MailboxError> {
+        range.as_ref().map(|range| self.get_header(range)).transpose()
+    }
+
+    /// Content at `range` with the mbox quoting reversed and the transfer encoding decoded.
+    fn decode_content(&self, range: &Range<usize>, content_transfer_encoding: &str) -> Result<Cow<'a, [u8]>, MailboxError> {
+        match self.unescape(&self.data[range.start..range.end]) {
+            Cow::Borrowed(content) => TransferEncoding::detect(content_transfer_encoding, content)?.decode(content),
+            Cow::Owned(content) => TransferEncoding::detect(content_transfer_encoding, &content)?.decode(&content)
+                .map(|decoded| Cow::Owned(decoded.into_owned())),
+        }
+    }
+
+    /// Start of the message after its mbox `From ` line.
+    fn content_start(&self, email_ptr: &EmailFilePtr) -> usize {
+        match self.format {
+            Some(_) => next_line(self.data, email_ptr.email.start).1.min(email_ptr.email.end),
+            None => email_ptr.email.start,
+        }
+    }
+
+    /// Content with the mbox quoting reversed.
+    fn unescape(&self, content: &'a [u8]) -> Cow<'a, [u8]> {
+        self.format.map_or(Cow::Borrowed(content), |format| format.unescape(content))
+    }
+
+    /// Decoded body converted to UTF-8, with a flag set when the text had to be repaired. Borrowed when
+    /// the body needs neither unquoting, transfer decoding nor charset conversion.
+    fn get_body(&self, body_ptr: &BodyFilePtr) -> Result<(Cow<'a, str>, bool), MailboxError> {
+        let charset = body_ptr.charset.as_deref();
+        match self.decode_content(&body_ptr.content, &body_ptr.content_transfer_encoding)? {
+            Cow::Borrowed(decoded) => Ok(decode_charset(decoded, charset)),
+            Cow::Owned(decoded) => {
+                let (text, repaired) = decode_charset(&decoded, charset);
+                Ok((Cow::Owned(text.into_owned()), repaired))
+            }
+        }
+    }
+
+}
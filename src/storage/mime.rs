@@ -0,0 +1,457 @@
+use std::ops::Range;
+
+use rfc2047_decoder::{Decoder, RecoverStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::{mailbox::Attachment, storage::{encoding::{decode_charset, TransferEncoding}, header::{next_line, HeaderBlock, HeaderFilePtr}}};
+
+/// Maximum depth of nested multipart, deeper parts are kept as opaque leaves.
+const MAX_DEPTH: usize = 32;
+
+/// Value of a MIME header with parameters, like `Content-Type` or `Content-Disposition`.
+#[derive(Debug, PartialEq)]
+pub struct MimeHeader {
+    /// Lower-cased value before the first `;`, `text/plain` or `attachment` for example.
+    pub value: String,
+    /// Parameters with lower-cased names and unquoted values.
+    pub params: Vec<(String, String)>,
+}
+
+impl MimeHeader {
+
+    pub fn parse(raw: &str) -> Self {
+        let mut fields = split_unquoted(raw, ';').into_iter();
+        let value = fields.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let params = fields
+            .filter_map(|field| field.split_once('=')
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), unquote(value.trim()))))
+            .collect();
+        MimeHeader { value, params }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter()
+            .find(|(param_name, _)| param_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Value of a parameter, combining RFC 2231 continuations (`name*0`, `name*1*`...) and decoding
+    /// RFC 2231 extended values (`name*=utf-8''caf%C3%A9`) or RFC 2047 encoded words.
+    pub fn decoded_param(&self, name: &str) -> Option<String> {
+        if let Some(extended) = self.param(&format!("{name}*")) {
+            let (charset, value) = split_extended(extended);
+            return Some(decode_charset(&percent_decode(value), charset).0.into_owned());
+        }
+        let mut charset = None;
+        let mut bytes = vec![];
+        for idx in 0.. {
+            if let Some(extended) = self.param(&format!("{name}*{idx}*")) {
+                let value = if idx == 0 {
+                    let (first_charset, value) = split_extended(extended);
+                    charset = first_charset;
+                    value
+                } else {
+                    extended
+                };
+                bytes.extend(percent_decode(value));
+            } else if let Some(value) = self.param(&format!("{name}*{idx}")) {
+                bytes.extend_from_slice(value.as_bytes());
+            } else {
+                break;
+            }
+        }
+        if !bytes.is_empty() {
+            return Some(decode_charset(&bytes, charset).0.into_owned());
+        }
+        self.param(name).map(|value| Decoder::new()
+            .too_long_encoded_word_strategy(RecoverStrategy::Skip)
+            .decode(value.as_bytes())
+            .unwrap_or_else(|_| value.to_string()))
+    }
+
+}
+
+/// Split a RFC 2231 extended value `charset'language'value` into charset and value.
+fn split_extended(extended: &str) -> (Option<&str>, &str) {
+    let mut fields = extended.splitn(3, '\'');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(charset), Some(_), Some(value)) => ((!charset.is_empty()).then_some(charset), value),
+        _ => (None, extended),
+    }
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'%' && idx + 2 < bytes.len()
+                && let Ok(hex) = std::str::from_utf8(&bytes[idx + 1..idx + 3])
+                && let Ok(byte) = u8::from_str_radix(hex, 16) {
+            res.push(byte);
+            idx += 3;
+        } else {
+            res.push(bytes[idx]);
+            idx += 1;
+        }
+    }
+    res
+}
+
+/// Split `raw` on `separator` outside of quoted strings.
+fn split_unquoted(raw: &str, separator: char) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (idx, c) in raw.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            _ if c == separator && !in_quotes => {
+                fields.push(&raw[start..idx]);
+                start = idx + c.len_utf8();
+            },
+            _ => (),
+        }
+    }
+    fields.push(&raw[start..]);
+    fields
+}
+
+fn unquote(value: &str) -> String {
+    if let Some(quoted) = value.strip_prefix('"') {
+        let quoted = quoted.strip_suffix('"').unwrap_or(quoted);
+        let mut res = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => res.extend(chars.next()),
+                _ => res.push(c),
+            }
+        }
+        res
+    } else {
+        value.to_string()
+    }
+}
+
+/// Node of the MIME tree of a message. Ranges are positions in the mailbox.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MimePart {
+    pub headers: Vec<HeaderFilePtr>,
+    /// Lower-cased media type, `text/plain` when the part has no `Content-Type`.
+    pub content_type: String,
+    /// `charset` parameter of the `Content-Type`.
+    pub charset: Option<String>,
+    /// Lower-cased transfer encoding, `7bit` when the part has no `Content-Transfer-Encoding`.
+    pub content_transfer_encoding: String,
+    /// Lower-cased disposition type (`inline`, `attachment`) if any.
+    pub disposition: Option<String>,
+    /// Whole part, headers included.
+    pub part: Range<usize>,
+    /// Content of the part, after the blank line ending the headers.
+    pub body: Range<usize>,
+    pub preamble: Option<Range<usize>>,
+    pub epilogue: Option<Range<usize>>,
+    pub children: Vec<MimePart>,
+}
+
+impl MimePart {
+
+    /// Parse the message or part at `range` of `data`, recursively for multipart content.
+    pub fn parse(data: &[u8], range: Range<usize>) -> Self {
+        Self::parse_part(data, range, "text/plain", 0)
+    }
+
+    fn parse_part(data: &[u8], range: Range<usize>, default_type: &str, depth: usize) -> Self {
+        let block = HeaderBlock::parse(&data[range.start..range.end], range.start);
+        let header_value = |name: &str| block.find(data, name)
+            .map(|header| MimeHeader::parse(&String::from_utf8_lossy(&header.unfolded_value(data))));
+        let content_type = header_value("Content-Type");
+        let mut part = MimePart {
+            content_type: content_type.as_ref()
+                .map(|ct| ct.value.clone())
+                .filter(|ct| ct.contains('/'))
+                .unwrap_or_else(|| default_type.to_string()),
+            charset: content_type.as_ref().and_then(|ct| ct.param("charset")).map(str::to_string),
+            content_transfer_encoding: header_value("Content-Transfer-Encoding")
+                .map(|cte| cte.value)
+                .unwrap_or_else(|| "7bit".to_string()),
+            disposition: header_value("Content-Disposition").map(|cd| cd.value),
+            headers: block.headers,
+            body: Range { start: block.body_start, end: range.end },
+            part: range,
+            preamble: None,
+            epilogue: None,
+            children: vec![],
+        };
+        if part.is_multipart() && depth < MAX_DEPTH
+                && let Some(boundary) = content_type.as_ref().and_then(|ct| ct.param("boundary"))
+                && !boundary.is_empty() {
+            let children_type = if part.content_type == "multipart/digest" { "message/rfc822" } else { "text/plain" };
+            part.parse_children(data, boundary.as_bytes(), children_type, depth);
+        }
+        part
+    }
+
+    fn parse_children(&mut self, data: &[u8], boundary: &[u8], children_type: &str, depth: usize) {
+        let mut pos = self.body.start;
+        let mut current: Option<usize> = None;
+
+        while pos < self.body.end {
+            let (line, next) = next_line(&data[..self.body.end], pos);
+            if let Some(is_close) = Self::delimiter(&data[line.start..line.end], boundary) {
+                // the line ending before a delimiter belongs to the delimiter
+                let content_end = Self::strip_line_ending(data, self.body.start, line.start);
+                match current {
+                    Some(start) => self.children.push(Self::parse_part(data,
+                        Range { start, end: content_end.max(start) }, children_type, depth + 1)),
+                    None => self.preamble = Some(Range { start: self.body.start, end: content_end }),
+                }
+                if is_close {
+                    self.epilogue = Some(Range { start: next, end: self.body.end });
+                    return;
+                }
+                current = Some(next);
+            }
+            pos = next;
+        }
+        // missing close delimiter, the last part runs up to the end of the body
+        if let Some(start) = current {
+            self.children.push(Self::parse_part(data, Range { start, end: self.body.end }, children_type, depth + 1));
+        }
+    }
+
+    /// `Some(true)` for a close delimiter, `Some(false)` for a delimiter, `None` otherwise.
+    fn delimiter(line: &[u8], boundary: &[u8]) -> Option<bool> {
+        let rest = line.strip_prefix(b"--")?.strip_prefix(boundary)?;
+        let (is_close, padding) = match rest.strip_prefix(b"--") {
+            Some(padding) => (true, padding),
+            None => (false, rest),
+        };
+        padding.iter().all(|&c| c == b' ' || c == b'\t').then_some(is_close)
+    }
+
+    fn strip_line_ending(data: &[u8], min: usize, pos: usize) -> usize {
+        let mut end = pos;
+        if end > min && data[end - 1] == b'\n' {
+            end -= 1;
+            if end > min && data[end - 1] == b'\r' {
+                end -= 1;
+            }
+        }
+        end
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.content_type.starts_with("multipart/")
+    }
+
+    /// Whether a multipart of the tree has no close delimiter, its last part running up to the end of its parent.
+    pub fn missing_close_delimiter(&self) -> bool {
+        (self.is_multipart() && !self.children.is_empty() && self.epilogue.is_none())
+            || self.children.iter().any(MimePart::missing_close_delimiter)
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.disposition.as_deref() == Some("attachment")
+    }
+
+    /// Parts of the tree in depth-first order, starting with this one.
+    pub fn parts(&self) -> Vec<&MimePart> {
+        let mut parts = vec![self];
+        parts.extend(self.children.iter().flat_map(|child| child.parts()));
+        parts
+    }
+
+    /// Leaf parts of the tree, in order of appearance.
+    pub fn leaves(&self) -> Vec<&MimePart> {
+        if self.children.is_empty() {
+            vec![self]
+        } else {
+            self.children.iter().flat_map(|child| child.leaves()).collect()
+        }
+    }
+
+    fn is_text_body(&self) -> bool {
+        self.content_type.starts_with("text/") && !self.is_attachment() && !self.body.is_empty()
+    }
+
+    /// Leaf parts displayable as the message body : non empty text parts not sent as attachments.
+    pub fn text_parts(&self) -> Vec<&MimePart> {
+        self.leaves().into_iter()
+            .filter(|part| part.is_text_body())
+            .collect()
+    }
+
+    /// Leaf parts which are not part of the message body : parts sent as attachments and non text
+    /// parts, in order of appearance.
+    pub fn attachment_parts(&self) -> Vec<&MimePart> {
+        self.leaves().into_iter()
+            .filter(|part| !part.is_multipart() && (part.is_attachment() || !part.content_type.starts_with("text/")))
+            .collect()
+    }
+
+    fn header_value(&self, data: &[u8], name: &str) -> Option<String> {
+        self.headers.iter()
+            .find(|header| header.is(data, name))
+            .map(|header| String::from_utf8_lossy(&header.unfolded_value(data)).trim().to_string())
+    }
+
+    /// Attachment description of this part, without decoding its content.
+    pub fn attachment(&self, data: &[u8]) -> Attachment {
+        let filename = self.header_value(data, "Content-Disposition")
+            .and_then(|cd| MimeHeader::parse(&cd).decoded_param("filename"))
+            .or_else(|| self.header_value(data, "Content-Type")
+                .and_then(|ct| MimeHeader::parse(&ct).decoded_param("name")));
+        let content = &data[self.body.start..self.body.end];
+        Attachment {
+            filename,
+            mime_type: self.content_type.clone(),
+            size: TransferEncoding::parse(&self.content_transfer_encoding)
+                .map(|encoding| encoding.decoded_len(content))
+                .unwrap_or(content.len()),
+            content_id: self.header_value(data, "Content-ID")
+                .map(|cid| cid.trim_start_matches('<').trim_end_matches('>').to_string()),
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NESTED: &[u8] = b"From: a@b.c\n\
+Content-Type: multipart/mixed; boundary=\"outer\"\n\
+\n\
+preamble\n\
+--outer\n\
+Content-Type: multipart/alternative;\n boundary=inner\n\
+\n\
+--inner\n\
+Content-Type: text/plain; charset=\"UTF-8\"\n\
+\n\
+plain text\n\
+--inner\n\
+Content-Type: text/html\n\
+Content-Transfer-Encoding: Quoted-Printable\n\
+\n\
+<p>html</p>\n\
+--inner--\n\
+\n\
+--outer\n\
+Content-Type: text/plain; name=\"a.txt\"\n\
+Content-Disposition: attachment; filename=\"a.txt\"\n\
+\n\
+attached\n\
+--outer--\n\
+epilogue\n";
+
+    fn body<'a>(data: &'a [u8], part: &MimePart) -> &'a [u8] {
+        &data[part.body.start..part.body.end]
+    }
+
+    #[test]
+    fn test_mime_header_parse() {
+        let header = MimeHeader::parse("Text/Plain; charset=\"UTF-8\"; Name=\"a; \\\"b\\\".txt\"");
+        assert_eq!("text/plain", header.value);
+        assert_eq!(Some("UTF-8"), header.param("charset"));
+        assert_eq!(Some("a; \"b\".txt"), header.param("name"));
+        assert_eq!(None, header.param("boundary"));
+    }
+
+    #[test]
+    fn test_decoded_param_rfc2231() {
+        let header = MimeHeader::parse("attachment; filename*=utf-8'fr'na%C3%AFve%20file.txt");
+        assert_eq!(Some("naïve file.txt".to_string()), header.decoded_param("filename"));
+
+        let header = MimeHeader::parse("attachment; filename*0*=iso-8859-1''r%E9sum; filename*1=\"e final\"; filename*2*=%2Epdf");
+        assert_eq!(Some("résume final.pdf".to_string()), header.decoded_param("filename"));
+
+        let header = MimeHeader::parse("attachment; filename=\"=?UTF-8?Q?r=C3=A9sum=C3=A9.pdf?=\"");
+        assert_eq!(Some("résumé.pdf".to_string()), header.decoded_param("filename"));
+        assert_eq!(None, header.decoded_param("name"));
+    }
+
+    #[test]
+    fn test_attachment_parts() {
+        let part = MimePart::parse(NESTED, 0..NESTED.len());
+        let attachments = part.attachment_parts();
+        assert_eq!(1, attachments.len());
+        let attachment = attachments[0].attachment(NESTED);
+        assert_eq!(Some("a.txt".to_string()), attachment.filename);
+        assert_eq!("text/plain", attachment.mime_type);
+        assert_eq!(8, attachment.size);
+        assert_eq!(None, attachment.content_id);
+
+        let single = b"Subject: test\n\nHello\n";
+        assert!(MimePart::parse(single, 0..single.len()).attachment_parts().is_empty());
+        let single = b"Content-Type: application/pdf\nContent-Transfer-Encoding: base64\n\nJVBERg==\n";
+        let attachments = MimePart::parse(single, 0..single.len()).attachment_parts()
+            .into_iter().map(|part| part.attachment(single)).collect::<Vec<_>>();
+        assert_eq!(1, attachments.len());
+        assert_eq!(4, attachments[0].size);
+    }
+
+    #[test]
+    fn test_parse_single_part() {
+        let data = b"From: a@b.c\nSubject: test\n\nHello\n";
+        let part = MimePart::parse(data, 0..data.len());
+        assert_eq!("text/plain", part.content_type);
+        assert_eq!("7bit", part.content_transfer_encoding);
+        assert!(part.children.is_empty());
+        assert_eq!(b"Hello\n", body(data, &part));
+    }
+
+    #[test]
+    fn test_parse_nested_multipart() {
+        let part = MimePart::parse(NESTED, 0..NESTED.len());
+        assert_eq!("multipart/mixed", part.content_type);
+        assert_eq!(b"preamble", &NESTED[part.preamble.clone().unwrap()]);
+        assert_eq!(b"epilogue\n", &NESTED[part.epilogue.clone().unwrap()]);
+        assert_eq!(2, part.children.len());
+
+        let alternative = &part.children[0];
+        assert_eq!("multipart/alternative", alternative.content_type);
+        assert_eq!(2, alternative.children.len());
+        assert_eq!(b"plain text", body(NESTED, &alternative.children[0]));
+        assert_eq!(Some("UTF-8"), alternative.children[0].charset.as_deref());
+        assert_eq!(None, alternative.children[1].charset);
+        assert_eq!("text/html", alternative.children[1].content_type);
+        assert_eq!("quoted-printable", alternative.children[1].content_transfer_encoding);
+        assert_eq!(b"<p>html</p>", body(NESTED, &alternative.children[1]));
+
+        assert!(part.children[1].is_attachment());
+        assert_eq!(b"attached", body(NESTED, &part.children[1]));
+    }
+
+    #[test]
+    fn test_text_parts_skip_attachments() {
+        let part = MimePart::parse(NESTED, 0..NESTED.len());
+        let text_parts = part.text_parts();
+        assert_eq!(2, text_parts.len());
+        assert_eq!("text/plain", text_parts[0].content_type);
+        assert_eq!("text/html", text_parts[1].content_type);
+    }
+
+    #[test]
+    fn test_parse_missing_close_delimiter() {
+        let data = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\n\r\nfirst\r\n--b \r\n\r\nsecond\r\n";
+        let part = MimePart::parse(data, 0..data.len());
+        assert_eq!(2, part.children.len());
+        assert_eq!(b"first", body(data, &part.children[0]));
+        assert_eq!(b"second\r\n", body(data, &part.children[1]));
+        assert!(part.epilogue.is_none());
+    }
+
+    #[test]
+    fn test_boundary_prefix_is_not_delimiter() {
+        assert_eq!(Some(false), MimePart::delimiter(b"--abc", b"abc"));
+        assert_eq!(Some(true), MimePart::delimiter(b"--abc--", b"abc"));
+        assert_eq!(None, MimePart::delimiter(b"--abcdef", b"abc"));
+        assert_eq!(None, MimePart::delimiter(b"abc", b"abc"));
+    }
+}
@@ -1,24 +1,92 @@
-use std::{error::Error, fmt::{Debug, Display}};
+use std::{borrow::Cow, error::Error, fmt::{Debug, Display}, fs::{self, File, OpenOptions}, io::{BufWriter, Write}, path::Path, sync::atomic::{AtomicUsize, Ordering}};
 
-use crate::Email;
+use crate::{mailbox::Attachment, storage::view::EmailView, Email};
 
+pub mod address;
+pub mod compression;
+pub mod date;
+pub mod eml;
+pub mod encoding;
 pub mod file;
+pub mod format;
+pub mod header;
+pub mod index;
+pub mod lexer;
+pub mod maildir;
+pub mod message;
+pub mod mime;
+pub mod report;
+pub mod view;
+pub mod writer;
 
 // pub struct FileSource<'a>(pub &'a str);
 
-#[derive(Debug, PartialEq, strum::Display)]
+#[derive(Debug, Clone, Copy, PartialEq, strum::Display)]
 pub enum MailboxError {
     MboxFileNotFound,
     MboxParseError,
     MboxValidationError,
+    MboxIndexError,
+    MboxDecompressError,
+    MboxWriteError,
+    MaildirNotFound,
+    EmlDirectoryNotFound,
     EmailNotFound,
+    EmailFileChanged,
+    AttachmentNotFound,
+    PartNotFound,
     DecodeQuotedPrintableError,
+    DecodeBase64Error,
+    DecodeUuencodeError,
+    UnknownTransferEncodingError,
     UTF8EncodeError,
     EncodedWordDecodeError,
 }
 
 impl Error for MailboxError {}
 
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Write the file `path` with `write` to a temporary file renamed once complete, so that a partial file is
+/// never read and the previous file stays readable meanwhile. The temporary file is only readable by its
+/// owner when `private`, and removed on failure. I/O errors are returned as `error`.
+pub(crate) fn write_atomically<T>(path: &Path, private: bool, error: MailboxError,
+        write: impl FnOnce(&mut BufWriter<File>) -> Result<T, MailboxError>) -> Result<T, MailboxError> {
+    let tmp_path = format!("{}.{}-{}.tmp", path.display(), std::process::id(), TMP_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let res = options.open(&tmp_path).or(Err(error))
+        .and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            let res = write(&mut writer)?;
+            writer.flush().or(Err(error))?;
+            Ok(res)
+        })
+        .and_then(|res| fs::rename(&tmp_path, path).map(|_| res).or(Err(error)));
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    res
+}
+
+/// Temporary directory with `files`, given by their relative path and content, removed when dropped.
+#[cfg(test)]
+pub(crate) fn tmp_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::Builder::new().prefix("mbox-viewer-").tempdir().unwrap();
+    for (file_path, content) in files {
+        let file_path = dir.path().join(file_path);
+        std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+        std::fs::write(file_path, content).unwrap();
+    }
+    dir
+}
+
 pub trait MailStorageRepository: Debug {
     type EmailId: PartialOrd + Display;
 
@@ -28,4 +96,32 @@ pub trait MailStorageRepository: Debug {
 
     fn emails(&self) -> impl Iterator<Item = Email<Self::EmailId>>;
 
+    /// View of the email decoding its fields on first access.
+    fn email_view(&self, id: &Self::EmailId) -> Result<EmailView<'_, Self::EmailId>, MailboxError>;
+
+    /// Views of all emails, as `emails` without decoding the fields that are not read.
+    fn email_views(&self) -> impl Iterator<Item = EmailView<'_, Self::EmailId>>;
+
+    /// Decoded value of the first header field `name` of the email, for headers without an `Email` field.
+    fn header(&self, id: &Self::EmailId, name: &str) -> Result<Option<String>, MailboxError>;
+
+    /// Original bytes of the email as delivered, without the mbox `From ` line and quoting.
+    /// A `Cow` rather than a `&[u8]` : unquoting the `>From ` lines of an mboxo or mboxrd message
+    /// builds new bytes, and Maildir and EML storages read each file into an owned buffer. The bytes
+    /// are borrowed from the mapping of an mbox when it has no quoting to reverse.
+    fn raw_email(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError>;
+
+    /// Header section of the email, borrowed or owned as in `raw_email`.
+    fn raw_headers(&self, id: &Self::EmailId) -> Result<Cow<'_, [u8]>, MailboxError>;
+
+    /// Part at `index` of the MIME tree of the email in depth-first order, 0 being the whole email,
+    /// with its headers and without decoding its content, borrowed or owned as in `raw_email`.
+    fn raw_part(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError>;
+
+    fn attachments(&self, id: &Self::EmailId) -> Result<Vec<Attachment>, MailboxError>;
+
+    /// Decoded content of the attachment at `index` in the email attachments list,
+    /// borrowed from the storage when it is not transfer-encoded.
+    fn attachment_content(&self, id: &Self::EmailId, index: usize) -> Result<Cow<'_, [u8]>, MailboxError>;
+
 }
@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// What happened to a message with an issue.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, strum::Display)]
+pub enum ParseIssueKind {
+    /// The message was not loaded.
+    Skipped,
+    /// The message was loaded, part of it being guessed.
+    Repaired,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, strum::Display)]
+pub enum ParseReason {
+    MissingField,
+    InvalidField,
+    /// A multipart has no close delimiter, its last part runs up to the end of the message.
+    MissingCloseDelimiter,
+}
+
+/// Issue found while parsing a message, a message having one issue per missing or invalid field.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParseIssue {
+    /// Position of the message in the mailbox, its `From ` line for mbox files.
+    pub offset: usize,
+    pub kind: ParseIssueKind,
+    pub reason: ParseReason,
+    /// Name of the header field concerned, if any.
+    pub field: Option<String>,
+}
+
+impl ParseIssue {
+
+    pub(crate) fn new(offset: usize, kind: ParseIssueKind, reason: ParseReason, field: Option<&str>) -> Self {
+        ParseIssue { offset, kind, reason, field: field.map(str::to_string) }
+    }
+
+}
+
+/// Messages skipped or repaired while parsing a mailbox, to audit how much of it was loaded.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct ParseReport {
+    /// Issues in mailbox order.
+    pub issues: Vec<ParseIssue>,
+}
+
+impl ParseReport {
+
+    /// Number of messages not loaded.
+    pub fn skipped(&self) -> usize {
+        self.count(ParseIssueKind::Skipped)
+    }
+
+    /// Number of messages loaded with a repair.
+    pub fn repaired(&self) -> usize {
+        self.count(ParseIssueKind::Repaired)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    fn count(&self, kind: ParseIssueKind) -> usize {
+        self.issues.iter()
+            .filter(|issue| issue.kind == kind)
+            .map(|issue| issue.offset)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+}
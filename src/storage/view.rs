@@ -0,0 +1,89 @@
+use std::{borrow::Cow, cell::OnceCell};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{mailbox::Address, storage::{format::MboxFormat, message::{EmailFilePtr, MessageReader}, MailboxError}, Email};
+
+/// Email read on demand from its storage : fields are decoded on first access then cached, and borrow
+/// the storage bytes when they need no decoding. Cheaper than `Email` when only a few fields are read,
+/// e.g. a body for indexing.
+pub struct EmailView<'a, EmailId> {
+    id: EmailId,
+    /// Bytes `email_ptr` points into, borrowed from an mbox mapping or read from a message file.
+    data: Cow<'a, [u8]>,
+    format: Option<MboxFormat>,
+    email_ptr: &'a EmailFilePtr,
+    from: OnceCell<Option<Cow<'a, str>>>,
+    subject: OnceCell<Option<Cow<'a, str>>>,
+    body_text: OnceCell<Option<Cow<'a, str>>>,
+    body_html: OnceCell<Option<Cow<'a, str>>>,
+}
+
+impl<'a, EmailId> EmailView<'a, EmailId> {
+
+    pub(crate) fn new(id: EmailId, data: Cow<'a, [u8]>, format: Option<MboxFormat>, email_ptr: &'a EmailFilePtr) -> Self {
+        EmailView {
+            id, data, format, email_ptr,
+            from: OnceCell::new(),
+            subject: OnceCell::new(),
+            body_text: OnceCell::new(),
+            body_html: OnceCell::new(),
+        }
+    }
+
+    pub fn id(&self) -> &EmailId {
+        &self.id
+    }
+
+    pub fn into_id(self) -> EmailId {
+        self.id
+    }
+
+    /// Date with the offset written by the sender.
+    pub fn datetime(&self) -> DateTime<FixedOffset> {
+        self.email_ptr.datetime
+    }
+
+    /// Decoded `From` header, `None` when its encoded words cannot be decoded.
+    pub fn from(&self) -> Option<&str> {
+        let email_ptr = self.email_ptr;
+        self.from.get_or_init(|| self.decode(|reader| reader.header_value(&email_ptr.from).ok())).as_deref()
+    }
+
+    /// First mailbox of the `From` header, parsed on each call.
+    pub fn sender_address(&self) -> Option<Address> {
+        MessageReader::new(&self.data, self.format).sender_address(self.email_ptr)
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        let email_ptr = self.email_ptr;
+        self.subject.get_or_init(|| self.decode(|reader| email_ptr.subject.as_ref()
+            .and_then(|range| reader.header_value(range).ok()))).as_deref()
+    }
+
+    /// First text body, `None` when it cannot be decoded, `to_email` reporting why in `Email::body_error`.
+    pub fn body_text(&self) -> Option<&str> {
+        let email_ptr = self.email_ptr;
+        self.body_text.get_or_init(|| self.decode(|reader| reader.body_text(email_ptr).ok().flatten().map(|(text, _)| text))).as_deref()
+    }
+
+    /// First HTML body, as in `body_text`.
+    pub fn body_html(&self) -> Option<&str> {
+        let email_ptr = self.email_ptr;
+        self.body_html.get_or_init(|| self.decode(|reader| reader.body_html(email_ptr).ok().flatten().map(|(html, _)| html))).as_deref()
+    }
+
+    /// Whole email with all its fields decoded.
+    pub fn to_email(&self) -> Result<Email<EmailId>, MailboxError> where EmailId: Clone {
+        MessageReader::new(&self.data, self.format).email(self.id.clone(), self.email_ptr)
+    }
+
+    /// Value decoded by `decode`, owned when the view owns its bytes.
+    fn decode(&self, decode: impl for<'d> Fn(&MessageReader<'d>) -> Option<Cow<'d, str>>) -> Option<Cow<'a, str>> {
+        match &self.data {
+            Cow::Borrowed(data) => decode(&MessageReader::new(data, self.format)),
+            Cow::Owned(data) => decode(&MessageReader::new(data, self.format)).map(|value| Cow::Owned(value.into_owned())),
+        }
+    }
+
+}
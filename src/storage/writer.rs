@@ -0,0 +1,144 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, instrument};
+
+use crate::{storage::{format::MboxFormat, header::next_line, write_atomically, MailboxError}, MailStorageRepository};
+
+/// Envelope sender of the `From ` line when the message gives none.
+const UNKNOWN_SENDER: &str = "MAILER-DAEMON";
+
+/// Writer of raw messages to an mbox, generating their `From ` line and quoting the `From ` lines
+/// of their content for the chosen variant.
+pub struct MboxWriter<W: Write> {
+    writer: W,
+    format: MboxFormat,
+}
+
+impl MboxWriter<BufWriter<File>> {
+
+    /// Create the mbox file `file_path`, truncated if it exists.
+    pub fn create(file_path: &str, format: MboxFormat) -> Result<Self, MailboxError> {
+        let file = File::create(file_path).or(Err(MailboxError::MboxWriteError))?;
+        Self::new(BufWriter::new(file), format)
+    }
+
+}
+
+impl<W: Write> MboxWriter<W> {
+
+    /// Only mboxo and mboxrd can be written : `Content-Length` variants would need headers to be rewritten.
+    pub fn new(writer: W, format: MboxFormat) -> Result<Self, MailboxError> {
+        if format.use_content_length() {
+            return Err(MailboxError::MboxWriteError);
+        }
+        Ok(MboxWriter { writer, format })
+    }
+
+    /// Append the email `id` of `storage`, its envelope sender taken from `Return-Path` or `From`.
+    /// Bodies are not decoded, so that an email with an undecodable body is still written.
+    pub fn write_email<S: MailStorageRepository>(&mut self, storage: &S, id: &S::EmailId) -> Result<(), MailboxError> {
+        let view = storage.email_view(id)?;
+        let sender = storage.header(id, "Return-Path")?
+            .map(|return_path| return_path.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .or_else(|| view.sender_address().map(|address| address.address))
+            .filter(|sender| !sender.is_empty() && !sender.contains(char::is_whitespace))
+            .unwrap_or(UNKNOWN_SENDER.to_string());
+        self.write_message(&sender, &view.datetime().to_utc(), &storage.raw_email(id)?)
+    }
+
+    /// Append a raw message, followed by an empty line. The line ending of its first line is used
+    /// for the `From ` line and the separator.
+    pub fn write_message(&mut self, sender: &str, datetime: &DateTime<Utc>, raw: &[u8]) -> Result<(), MailboxError> {
+        let (first_line, next) = next_line(raw, 0);
+        let eol: &[u8] = if next - first_line.end == 2 { b"\r\n" } else { b"\n" };
+        let from_line = format!("From {sender} {}", datetime.format("%a %b %e %H:%M:%S %Y"));
+        let mut res = Vec::with_capacity(raw.len() + from_line.len() + 2 * eol.len());
+        res.extend_from_slice(from_line.as_bytes());
+        res.extend_from_slice(eol);
+        let mut pos = 0;
+        while pos < raw.len() {
+            let (_, next) = next_line(raw, pos);
+            let line = &raw[pos..next];
+            if self.is_quoted(line) {
+                res.push(b'>');
+            }
+            res.extend_from_slice(line);
+            pos = next;
+        }
+        if !raw.is_empty() && !raw.ends_with(b"\n") {
+            res.extend_from_slice(eol);
+        }
+        res.extend_from_slice(eol);
+        self.writer.write_all(&res).or(Err(MailboxError::MboxWriteError))
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, MailboxError> {
+        self.writer.flush().or(Err(MailboxError::MboxWriteError))?;
+        Ok(self.writer)
+    }
+
+    /// Whether a content line gets one more `>` : `From ` lines for mboxo, also `>*From ` lines for mboxrd.
+    fn is_quoted(&self, line: &[u8]) -> bool {
+        match self.format {
+            MboxFormat::Mboxrd => line[line.iter().take_while(|&&c| c == b'>').count()..].starts_with(b"From "),
+            _ => line.starts_with(b"From "),
+        }
+    }
+
+}
+
+/// Write the emails `ids` of `storage` to a new mbox file, in the order given, and return their number.
+/// The file is written under a temporary name then renamed, so that exporting onto the mailbox being
+/// read does not truncate it while its emails are copied.
+#[instrument(skip(storage, ids))]
+pub fn export<'a, S: MailStorageRepository>(storage: &S, ids: impl IntoIterator<Item = &'a S::EmailId>, file_path: &str, format: MboxFormat) -> Result<usize, MailboxError>
+        where S::EmailId: 'a {
+    let count = write_atomically(Path::new(file_path), false, MailboxError::MboxWriteError, |file| {
+        let mut writer = MboxWriter::new(file, format)?;
+        let mut count = 0;
+        for id in ids {
+            writer.write_email(storage, id)?;
+            count += 1;
+        }
+        writer.finish()?;
+        Ok(count)
+    })?;
+    debug!("Exported {count} emails");
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: MboxFormat, raw: &[u8]) -> Vec<u8> {
+        let datetime = DateTime::parse_from_rfc2822("Mon, 4 Aug 2025 11:56:07 +0800").unwrap().to_utc();
+        let mut writer = MboxWriter::new(vec![], format).unwrap();
+        writer.write_message("jean@example.fr", &datetime, raw).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_write_message_quoting() {
+        let raw = b"Subject: a\n\nFrom here\n>From there\nFrom: not a header\n";
+        assert_eq!(b"From jean@example.fr Mon Aug  4 03:56:07 2025\nSubject: a\n\n>From here\n>From there\nFrom: not a header\n\n".as_slice(),
+            write(MboxFormat::Mboxo, raw));
+        assert_eq!(b"From jean@example.fr Mon Aug  4 03:56:07 2025\nSubject: a\n\n>From here\n>>From there\nFrom: not a header\n\n".as_slice(),
+            write(MboxFormat::Mboxrd, raw));
+    }
+
+    #[test]
+    fn test_write_message_line_endings() {
+        assert_eq!(b"From jean@example.fr Mon Aug  4 03:56:07 2025\r\nSubject: a\r\n\r\nbody\r\n\r\n".as_slice(),
+            write(MboxFormat::Mboxrd, b"Subject: a\r\n\r\nbody"));
+        assert_eq!(b"From jean@example.fr Mon Aug  4 03:56:07 2025\nSubject: a\n\nbody\n\n".as_slice(),
+            write(MboxFormat::Mboxrd, b"Subject: a\n\nbody\n"));
+    }
+
+    #[test]
+    fn test_content_length_format() {
+        assert!(MboxWriter::new(vec![], MboxFormat::Mboxcl2).is_err_and(|e| e == MailboxError::MboxWriteError));
+    }
+}
@@ -0,0 +1,469 @@
+use std::{collections::{HashMap, HashSet}, hash::Hash};
+
+use chrono::{DateTime, Utc};
+use tracing::{debug, instrument};
+
+use crate::{Email, MailStorageRepository};
+
+/// Prefixes of replies and forwards removed to compare subjects, `AW` and `WG` being german, `TR` french.
+const SUBJECT_PREFIXES: [&str; 6] = ["re", "fwd", "fw", "aw", "wg", "tr"];
+
+/// Message of a thread, or placeholder for a message referenced by replies but missing from the mailbox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadNode<EmailId> {
+    pub email_id: Option<EmailId>,
+    pub datetime: Option<DateTime<Utc>>,
+    /// Replies, oldest first.
+    pub children: Vec<ThreadNode<EmailId>>,
+}
+
+impl<EmailId> ThreadNode<EmailId> {
+
+    /// Ids of the emails of the subtree, depth first.
+    pub fn email_ids(&self) -> Vec<&EmailId> {
+        let mut ids: Vec<&EmailId> = self.email_id.iter().collect();
+        ids.extend(self.children.iter().flat_map(|child| child.email_ids()));
+        ids
+    }
+
+    fn first_activity(&self) -> Option<DateTime<Utc>> {
+        self.datetime.or_else(|| self.children.iter().filter_map(|child| child.first_activity()).min())
+    }
+
+    fn last_activity(&self) -> Option<DateTime<Utc>> {
+        self.children.iter().filter_map(|child| child.last_activity()).chain(self.datetime).max()
+    }
+
+}
+
+/// Conversation : a message and all its replies.
+#[derive(Debug, Clone)]
+pub struct Thread<EmailId> {
+    pub root: ThreadNode<EmailId>,
+    /// Subject of the first message without its reply prefixes.
+    pub subject: String,
+    /// Date of the most recent message of the thread.
+    pub last_activity: DateTime<Utc>,
+}
+
+impl<EmailId> Thread<EmailId> {
+
+    pub fn email_ids(&self) -> Vec<&EmailId> {
+        self.root.email_ids()
+    }
+
+    pub fn len(&self) -> usize {
+        self.email_ids().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+}
+
+/// Threads of a mailbox, built with the JWZ algorithm (https://www.jwz.org/doc/threading.html) from
+/// `Message-ID`, `In-Reply-To` and `References`, messages without references being grouped by subject.
+#[derive(Debug)]
+pub struct ThreadForest<EmailId> {
+    /// Threads ordered by date of their first message.
+    threads: Vec<Thread<EmailId>>,
+    thread_of_email: HashMap<EmailId, usize>,
+}
+
+impl<EmailId: Clone + Eq + Hash> ThreadForest<EmailId> {
+
+    #[instrument(skip_all)]
+    pub fn build<T: MailStorageRepository<EmailId = EmailId>>(storage: &T) -> Self {
+        Self::from_emails(storage.emails())
+    }
+
+    pub fn from_emails(emails: impl IntoIterator<Item = Email<EmailId>>) -> Self {
+        let mut builder = ThreadBuilder { containers: vec![], id_table: HashMap::new() };
+        for email in emails {
+            builder.add(email);
+        }
+        let threads = builder.finish();
+        let thread_of_email = threads.iter().enumerate()
+            .flat_map(|(idx, thread)| thread.email_ids().into_iter().map(move |id| (id.clone(), idx)))
+            .collect();
+        debug!("Built {} threads", threads.len());
+        ThreadForest { threads, thread_of_email }
+    }
+
+    /// Threads ordered by date of their first message.
+    pub fn threads(&self) -> &[Thread<EmailId>] {
+        &self.threads
+    }
+
+    /// Thread containing the email `id`.
+    pub fn thread_of(&self, id: &EmailId) -> Option<&Thread<EmailId>> {
+        self.thread_of_email.get(id).map(|idx| &self.threads[*idx])
+    }
+
+    /// Position in `threads` of the thread containing the email `id`, a thread id stable for the mailbox.
+    pub fn thread_index(&self, id: &EmailId) -> Option<usize> {
+        self.thread_of_email.get(id).copied()
+    }
+
+    /// Threads with the most recent activity first.
+    pub fn by_last_activity(&self) -> Vec<&Thread<EmailId>> {
+        let mut threads: Vec<_> = self.threads.iter().collect();
+        threads.sort_by_key(|thread| std::cmp::Reverse(thread.last_activity));
+        threads
+    }
+
+}
+
+/// Subject without its leading reply or forward prefixes (`Re:`, `Fwd:`, `RE[2]:`...), and whether
+/// there was any.
+pub fn base_subject(subject: &str) -> (&str, bool) {
+    let mut base = subject.trim();
+    let mut is_reply = false;
+    loop {
+        let Some(colon) = base.find(':') else {
+            return (base, is_reply);
+        };
+        let prefix = base[..colon].trim_end();
+        // `Re[2]` or `Re(2)` counters
+        let word = prefix.split(['[', '(']).next().unwrap_or_default();
+        let counter = &prefix[word.len()..];
+        let is_counter = counter.is_empty()
+            || (counter.len() > 2
+                && ((counter.starts_with('[') && counter.ends_with(']')) || (counter.starts_with('(') && counter.ends_with(')')))
+                && counter[1..counter.len() - 1].chars().all(|c| c.is_ascii_digit()));
+        if !is_counter || !SUBJECT_PREFIXES.iter().any(|known| word.eq_ignore_ascii_case(known)) {
+            return (base, is_reply);
+        }
+        base = base[colon + 1..].trim_start();
+        is_reply = true;
+    }
+}
+
+/// Message ids of a `Message-ID`, `In-Reply-To` or `References` value, comments and garbage ignored.
+fn parse_message_ids(value: &str) -> Vec<String> {
+    let ids: Vec<String> = value.split('<').skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty())
+        .collect();
+    if ids.is_empty() {
+        value.split_whitespace().take(1).map(str::to_string).collect()
+    } else {
+        ids
+    }
+}
+
+struct EmailInfo<EmailId> {
+    id: EmailId,
+    subject: String,
+    datetime: DateTime<Utc>,
+}
+
+struct Container<EmailId> {
+    email: Option<EmailInfo<EmailId>>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct ThreadBuilder<EmailId> {
+    containers: Vec<Container<EmailId>>,
+    id_table: HashMap<String, usize>,
+}
+
+impl<EmailId: Clone> ThreadBuilder<EmailId> {
+
+    fn container(&mut self, message_id: &str) -> usize {
+        if let Some(idx) = self.id_table.get(message_id) {
+            return *idx;
+        }
+        let idx = self.new_container(None);
+        self.id_table.insert(message_id.to_string(), idx);
+        idx
+    }
+
+    fn new_container(&mut self, email: Option<EmailInfo<EmailId>>) -> usize {
+        self.containers.push(Container { email, parent: None, children: vec![] });
+        self.containers.len() - 1
+    }
+
+    /// Whether `ancestor` is `idx` or one of its parents.
+    fn is_ancestor(&self, ancestor: usize, idx: usize) -> bool {
+        let mut current = Some(idx);
+        while let Some(idx) = current {
+            if idx == ancestor {
+                return true;
+            }
+            current = self.containers[idx].parent;
+        }
+        false
+    }
+
+    fn link(&mut self, parent: usize, child: usize) {
+        self.unlink(child);
+        self.containers[child].parent = Some(parent);
+        self.containers[parent].children.push(child);
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(parent) = self.containers[child].parent.take() {
+            self.containers[parent].children.retain(|idx| *idx != child);
+        }
+    }
+
+    /// Steps 1 and 2 of the algorithm : container of the email and links between its references.
+    fn add(&mut self, email: Email<EmailId>) {
+        let info = EmailInfo { id: email.id, subject: email.subject.unwrap_or_default(), datetime: email.datetime };
+        let message_id = email.message_id.as_deref().and_then(|value| parse_message_ids(value).into_iter().next());
+        let idx = match message_id {
+            Some(message_id) => {
+                let idx = self.container(&message_id);
+                if self.containers[idx].email.is_some() {
+                    // duplicated message id, the email is threaded on its own
+                    self.new_container(Some(info))
+                } else {
+                    self.containers[idx].email = Some(info);
+                    idx
+                }
+            }
+            None => self.new_container(Some(info)),
+        };
+
+        let mut references = email.references.as_deref().map(parse_message_ids).unwrap_or_default();
+        if let Some(in_reply_to) = email.in_reply_to.as_deref().and_then(|value| parse_message_ids(value).into_iter().next())
+                && references.last() != Some(&in_reply_to) {
+            references.push(in_reply_to);
+        }
+        let mut previous: Option<usize> = None;
+        for reference in &references {
+            let current = self.container(reference);
+            if let Some(previous) = previous && self.containers[current].parent.is_none()
+                    && !self.is_ancestor(current, previous) {
+                self.link(previous, current);
+            }
+            previous = Some(current);
+        }
+        // the references of the email itself are the most reliable for its parent
+        if let Some(parent) = previous && !self.is_ancestor(idx, parent) {
+            self.link(parent, idx);
+        }
+    }
+
+    /// Remaining steps : prune empty containers, group roots by subject and build the threads.
+    fn finish(mut self) -> Vec<Thread<EmailId>> {
+        let roots: Vec<usize> = (0..self.containers.len()).filter(|idx| self.containers[*idx].parent.is_none()).collect();
+        let roots: Vec<usize> = roots.into_iter().flat_map(|root| self.prune(root, true)).collect();
+        for root in &roots {
+            self.containers[*root].parent = None;
+        }
+        let roots = self.group_by_subject(roots);
+        let mut threads: Vec<Thread<EmailId>> = roots.into_iter()
+            .filter_map(|root| {
+                let subject = self.subject(root).map(|subject| base_subject(subject).0.to_string()).unwrap_or_default();
+                let root = self.node(root);
+                root.last_activity().map(|last_activity| Thread { root, subject, last_activity })
+            })
+            .collect();
+        threads.sort_by_key(|thread| thread.root.first_activity());
+        threads
+    }
+
+    /// Step 4 : containers replacing `idx` once empty containers are removed or replaced by their children.
+    /// Root containers with several children are kept to hold the thread together.
+    fn prune(&mut self, idx: usize, is_root: bool) -> Vec<usize> {
+        let children: Vec<usize> = std::mem::take(&mut self.containers[idx].children).into_iter()
+            .flat_map(|child| self.prune(child, false))
+            .collect();
+        for child in &children {
+            self.containers[*child].parent = Some(idx);
+        }
+        if self.containers[idx].email.is_none() && (!is_root || children.len() <= 1) {
+            return children;
+        }
+        self.containers[idx].children = children;
+        vec![idx]
+    }
+
+    /// Subject of the container email, or of its first child for an empty container.
+    fn subject(&self, idx: usize) -> Option<&str> {
+        match &self.containers[idx].email {
+            Some(email) => Some(&email.subject),
+            None => self.containers[idx].children.first().and_then(|child| self.subject(*child)),
+        }
+    }
+
+    /// Step 5 : merge roots with the same base subject, replies going under the original message.
+    fn group_by_subject(&mut self, roots: Vec<usize>) -> Vec<usize> {
+        let mut subject_table: HashMap<String, usize> = HashMap::new();
+        for root in &roots {
+            let Some((base, is_reply)) = self.subject(*root).map(base_subject) else { continue };
+            if base.is_empty() {
+                continue;
+            }
+            let base = base.to_lowercase();
+            let replace = subject_table.get(&base).is_none_or(|other| {
+                let other_is_reply = self.subject(*other).is_some_and(|subject| base_subject(subject).1);
+                (self.containers[*root].email.is_none() && self.containers[*other].email.is_some())
+                    || (other_is_reply && !is_reply)
+            });
+            if replace {
+                subject_table.insert(base, *root);
+            }
+        }
+
+        let mut merged = HashSet::new();
+        for root in &roots {
+            let Some(base) = self.subject(*root).map(|subject| base_subject(subject).0.to_lowercase()) else { continue };
+            let Some(&other) = subject_table.get(&base) else { continue };
+            if other == *root || merged.contains(root) {
+                continue;
+            }
+            let root_is_reply = self.subject(*root).is_some_and(|subject| base_subject(subject).1);
+            let other_is_reply = self.subject(other).is_some_and(|subject| base_subject(subject).1);
+            if self.containers[other].email.is_none() {
+                if self.containers[*root].email.is_none() {
+                    for child in self.containers[*root].children.clone() {
+                        self.link(other, child);
+                    }
+                } else {
+                    self.link(other, *root);
+                }
+            } else if root_is_reply && !other_is_reply {
+                self.link(other, *root);
+            } else {
+                // same subject without reply relationship, both go under a new empty container
+                let container = self.new_container(None);
+                self.link(container, other);
+                self.link(container, *root);
+                subject_table.insert(base, container);
+                merged.insert(other);
+            }
+            merged.insert(*root);
+        }
+        let mut roots: Vec<usize> = roots.into_iter().filter(|root| !merged.contains(root)).collect();
+        let root_set: HashSet<usize> = roots.iter().copied().collect();
+        let containers: Vec<usize> = subject_table.into_values()
+            .filter(|idx| self.containers[*idx].parent.is_none() && !root_set.contains(idx))
+            .collect();
+        roots.extend(containers);
+        roots
+    }
+
+    fn node(&self, idx: usize) -> ThreadNode<EmailId> {
+        let container = &self.containers[idx];
+        let mut children: Vec<ThreadNode<EmailId>> = container.children.iter().map(|child| self.node(*child)).collect();
+        children.sort_by_key(|child| child.first_activity());
+        ThreadNode {
+            email_id: container.email.as_ref().map(|email| email.id.clone()),
+            datetime: container.email.as_ref().map(|email| email.datetime),
+            children,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn email(id: usize, subject: &str, message_id: Option<&str>, in_reply_to: Option<&str>, references: Option<&str>) -> Email<usize> {
+        let datetime = Utc.with_ymd_and_hms(2025, 8, 1, 0, 0, 0).unwrap() + chrono::Duration::hours(id as i64);
+        Email {
+            id,
+            from: String::new(),
+            from_address: None,
+            datetime,
+            sender_datetime: datetime.fixed_offset(),
+            subject: Some(subject.to_string()),
+            to: vec![],
+            cc: vec![],
+            bcc: vec![],
+            reply_to: vec![],
+            message_id: message_id.map(str::to_string),
+            in_reply_to: in_reply_to.map(str::to_string),
+            references: references.map(str::to_string),
+            list_id: None,
+            body_text: None,
+            body_html: None,
+            body_error: None,
+            text_repaired: false,
+            attachments: vec![],
+        }
+    }
+
+    #[test]
+    fn test_base_subject() {
+        assert_eq!(("[VOTE] release", true), base_subject("Re: [VOTE] release"));
+        assert_eq!(("Hello", true), base_subject("RE[2]: Fwd:AW:  TR : Hello"));
+        assert_eq!(("Hello", false), base_subject(" Hello "));
+        assert_eq!(("Reminder: meeting", false), base_subject("Reminder: meeting"));
+        assert_eq!(("", true), base_subject("Re:"));
+    }
+
+    #[test]
+    fn test_parse_message_ids() {
+        assert_eq!(vec!["a@b", "c@d"], parse_message_ids("<a@b>\n <c@d> (comment)"));
+        assert_eq!(vec!["a@b"], parse_message_ids("a@b"));
+        assert!(parse_message_ids("  ").is_empty());
+    }
+
+    #[test]
+    fn test_thread_by_references() {
+        let forest = ThreadForest::from_emails(vec![
+            email(0, "Question", Some("<a@x>"), None, None),
+            email(1, "Re: Question", Some("<b@x>"), Some("<a@x>"), None),
+            email(2, "Re: Question", Some("<c@x>"), Some("<b@x>"), Some("<a@x> <b@x>")),
+            email(3, "Other", Some("<d@x>"), None, None),
+            email(4, "Re: Question", Some("<e@x>"), None, Some("<a@x>")),
+        ]);
+        assert_eq!(2, forest.threads().len());
+        let thread = forest.thread_of(&2).unwrap();
+        assert_eq!("Question", thread.subject);
+        assert_eq!(Some(0), thread.root.email_id);
+        assert_eq!(vec![&0, &1, &2, &4], thread.email_ids());
+        assert_eq!(Some(2), thread.root.children[0].children[0].email_id);
+        assert_eq!(email(4, "", None, None, None).datetime, thread.last_activity);
+        assert_eq!(vec![&3], forest.thread_of(&3).unwrap().email_ids());
+        assert_eq!(Some(0), forest.by_last_activity()[0].root.email_id);
+    }
+
+    #[test]
+    fn test_thread_missing_parent() {
+        let forest = ThreadForest::from_emails(vec![
+            email(0, "Re: Lost", Some("<b@x>"), Some("<missing@x>"), None),
+            email(1, "Re: Lost", Some("<c@x>"), Some("<missing@x>"), None),
+        ]);
+        assert_eq!(1, forest.threads().len());
+        let thread = &forest.threads()[0];
+        assert_eq!(None, thread.root.email_id);
+        assert_eq!(vec![&0, &1], thread.email_ids());
+    }
+
+    #[test]
+    fn test_thread_by_subject() {
+        let forest = ThreadForest::from_emails(vec![
+            email(0, "Re: Hello", None, None, None),
+            email(1, "Hello", Some("<a@x>"), None, None),
+            email(2, "AW: hello", None, None, None),
+            email(3, "Hello", Some("<b@x>"), None, None),
+            email(4, "Bye", None, None, None),
+        ]);
+        assert_eq!(2, forest.threads().len());
+        let thread = forest.thread_of(&0).unwrap();
+        assert_eq!(4, thread.len());
+        assert_eq!(None, thread.root.email_id);
+        assert!(forest.thread_of(&4).is_some_and(|thread| thread.len() == 1));
+    }
+
+    #[test]
+    fn test_thread_reference_loop() {
+        let forest = ThreadForest::from_emails(vec![
+            email(0, "A", Some("<a@x>"), Some("<b@x>"), None),
+            email(1, "B", Some("<b@x>"), Some("<a@x>"), None),
+        ]);
+        assert_eq!(1, forest.threads().len());
+        assert_eq!(2, forest.threads()[0].len());
+    }
+}
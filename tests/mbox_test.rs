@@ -1,6 +1,6 @@
-use std::time::Instant;
+use std::{borrow::Cow, time::Instant};
 
-use mbox_viewer::{embedding::{local::InternalEmbedder, Embedder}, mailbox::MailboxService, storage::{file::MboxFile, MailboxError}, MailStorageRepository};
+use mbox_viewer::{embedding::{local::InternalEmbedder, Embedder}, mailbox::MailboxService, storage::{compression, file::{MboxFile, Refresh}, format::MboxFormat, lexer::LexStats, message::ParseMode, writer, MailboxError}, threading::ThreadForest, MailStorageRepository};
 use tracing_test::traced_test;
 
 
@@ -29,16 +29,37 @@ fn test_count_emails() {
 fn test_get_email() {
     let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
     let email = email_repository.get_email(&1).unwrap();
-    assert_eq!("Re: [VOTE] Apache apisix-ingress-controller release version 2.0.0-rc3", email.subject);
+    assert_eq!(Some("Re: [VOTE] Apache apisix-ingress-controller release version 2.0.0-rc3"), email.subject.as_deref());
     assert!(email.body_html.is_none());
     assert!(email.body_text.is_some());
 }
 
+#[test]
+fn test_get_email_routing_and_threading_headers() {
+    let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
+    let email = email_repository.get_email(&1).unwrap();
+    assert_eq!(vec!["dev@apisix.apache.org"], email.to.iter().map(|address| address.address.as_str()).collect::<Vec<_>>());
+    assert_eq!(vec!["dev@apisix.apache.org"], email.reply_to.iter().map(|address| address.address.as_str()).collect::<Vec<_>>());
+    assert_eq!(Some("<dev.apisix.apache.org>".to_string()), email.list_id);
+    assert_eq!(Some("<CAKW95OsDk4U9vy2Rwig+7C3aJGdo+6phcd2C=kObW4rg7WDx4Q@mail.gmail.com>".to_string()), email.message_id);
+    assert_eq!(Some("<CAC_jp4hY0u+fo2sJz5rrJAUVXRJe_HNvo74T-xqWCXmPQu_u7g@mail.gmail.com>".to_string()), email.in_reply_to);
+    assert_eq!(Some("<CAAk8yMfBoT9MP4j9aEYMkwDZModV=RcU8bm3HjeG4JWcFJPORQ@mail.gmail.com> <CAC_jp4hY0u+fo2sJz5rrJAUVXRJe_HNvo74T-xqWCXmPQu_u7g@mail.gmail.com>".to_string()), email.references);
+    assert!(email.cc.is_empty());
+    assert!(email.bcc.is_empty());
+    let from = email.from_address.unwrap();
+    assert_eq!(Some("Zeping Bai".to_string()), from.name);
+    assert_eq!("bzp2010@apache.org", from.normalized());
+
+    assert_eq!(Some("bulk".to_string()), email_repository.header(&1, "precedence").unwrap());
+    assert_eq!(None, email_repository.header(&1, "X-Unknown").unwrap());
+    assert!(email_repository.header(&3, "Precedence").is_err_and(|e| e == MailboxError::EmailNotFound));
+}
+
 #[test]
 fn test_get_email_encoded_word_iso_8859_1() {
     let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
     let email = email_repository.get_email(&0).unwrap();
-    assert_eq!("[l.educonnect.cp] ÉduConnect - Perturbation sur le service d'authentification responsables et élèves", email.subject);
+    assert_eq!(Some("[l.educonnect.cp] ÉduConnect - Perturbation sur le service d'authentification responsables et élèves"), email.subject.as_deref());
     assert!(email.body_html.is_none());
     assert!(email.body_text.is_some());
 }
@@ -47,11 +68,270 @@ fn test_get_email_encoded_word_iso_8859_1() {
 fn test_get_email_encoded_word_utf8() {
     let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
     let email = email_repository.get_email(&2).unwrap();
-    assert_eq!("Modernisez vos processus RH sans complexité", email.subject);
+    assert_eq!(Some("Modernisez vos processus RH sans complexité"), email.subject.as_deref());
     assert!(email.body_html.is_none());
     assert!(email.body_text.is_some());
 }
 
+#[test]
+fn test_crlf_non_utf8_mbox_file() {
+    let email_repository = MboxFile::new("datasets/test_crlf.mbox").unwrap();
+    assert_eq!(2, email_repository.count_emails().unwrap());
+    assert_eq!(2, email_repository.lex_stats().messages);
+    assert_eq!(std::fs::metadata("datasets/test_crlf.mbox").unwrap().len() as usize, email_repository.lex_stats().bytes);
+    let email = email_repository.get_email(&0).unwrap();
+    assert_eq!(Some("Compte rendu de la réunion"), email.subject.as_deref());
+    assert!(email.body_text.unwrap().starts_with("Bonjour à tous,\r\n"));
+    let email = email_repository.get_email(&1).unwrap();
+    assert_eq!(Some("Re: Compte rendu"), email.subject.as_deref());
+    assert_eq!(Some("Merci Jean.".to_string()), email.body_text);
+}
+
+#[test]
+fn test_get_email_mboxrd_unescape() {
+    let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
+    let body = email_repository.get_email(&2).unwrap().body_text.unwrap();
+    assert!(body.contains("\nFrom A From Line\n"));
+    assert!(body.contains("\n>From A >From Line\n"));
+    assert!(body.contains("\n>>>>From This line has 4 > characters before From\n"));
+
+    let email_repository = MboxFile::with_format("datasets/test_lex.mbox", MboxFormat::Mboxo).unwrap();
+    let body = email_repository.get_email(&2).unwrap().body_text.unwrap();
+    assert!(body.contains("\n>From A From Line\n"));
+    assert!(body.contains("\n>>From A >From Line\n"));
+}
+
+#[test]
+fn test_emails_skip_unreadable_email() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("unreadable.mbox");
+    let path = path.to_str().unwrap();
+    let email = |subject: &str| format!("From a@b.c Mon Aug  4 11:56:07 2025\nFrom: a@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: {subject}\n\nbody\n\n");
+    std::fs::write(path, [email("first"), email("=?utf-8?B?@@@?="), email("third")].concat()).unwrap();
+    let email_repository = MboxFile::new(path).unwrap();
+    assert!(email_repository.get_email(&1).is_err_and(|e| e == MailboxError::EncodedWordDecodeError));
+    assert_eq!(vec![0, 2], email_repository.emails().map(|email| email.id).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_detect_format_after_given_format() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("given-format.mbox");
+    let path = path.to_str().unwrap();
+    std::fs::copy("datasets/test_lex.mbox", path).unwrap();
+    assert_eq!(MboxFormat::Mboxrd, MboxFile::new(path).unwrap().format());
+    // the index written for the given format is not reused when the format is to be detected
+    assert_eq!(MboxFormat::Mboxo, MboxFile::with_format(path, MboxFormat::Mboxo).unwrap().format());
+    let email_repository = MboxFile::new(path).unwrap();
+    assert_eq!(MboxFormat::Mboxrd, email_repository.format());
+    assert!(email_repository.get_email(&2).unwrap().body_text.unwrap().contains("\nFrom A From Line\n"));
+}
+
+#[test]
+fn test_refresh_detects_format_again() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("rewritten-format.mbox");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "From a@b.c Mon Aug  4 11:56:07 2025\nFrom: a@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: a\n\nbody\n").unwrap();
+    let mut email_repository = MboxFile::new(path).unwrap();
+    assert_eq!(MboxFormat::Mboxo, email_repository.format());
+    std::fs::copy("datasets/test_lex.mbox", path).unwrap();
+    assert_eq!(Refresh::Rewritten, email_repository.refresh().unwrap());
+    assert_eq!(MboxFormat::Mboxrd, email_repository.format());
+
+    // a given format is kept
+    let mut email_repository = MboxFile::with_format(path, MboxFormat::Mboxo).unwrap();
+    std::fs::write(path, "From a@b.c Mon Aug  4 11:56:07 2025\nFrom: a@b.c\nDate: Mon, 4 Aug 2025 11:56:07 +0800\nSubject: b\n\n>>From b\n").unwrap();
+    assert_eq!(Refresh::Rewritten, email_repository.refresh().unwrap());
+    assert_eq!(MboxFormat::Mboxo, email_repository.format());
+}
+
+#[test]
+fn test_get_attachments_without_attachment() {
+    let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
+    assert!(email_repository.attachments(&1).unwrap().is_empty());
+    assert!(email_repository.attachment_content(&1, 0).is_err_and(|e| e == MailboxError::AttachmentNotFound));
+    assert!(email_repository.attachments(&3).is_err_and(|e| e == MailboxError::EmailNotFound));
+}
+
+#[test]
+fn test_refresh_appended_mbox_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("refresh.mbox");
+    let path = path.to_str().unwrap();
+    let content = std::fs::read("datasets/test_crlf.mbox").unwrap();
+    std::fs::write(path, &content).unwrap();
+    let mut email_repository = MboxFile::new(path).unwrap();
+    assert_eq!(Refresh::Appended(vec![]), email_repository.refresh().unwrap());
+
+    // the last message has no final line ending, the appended bytes start by completing it
+    let mut appended = content.clone();
+    appended.extend_from_slice(b" A bientot.\r\nFrom b@example.com Mon Aug  4 11:56:07 2025\r\nFrom: b@example.com\r\nDate: Mon, 4 Aug 2025 11:56:07 +0800\r\nSubject: Nouveau\r\n\r\nNouveau message\r\n");
+    std::fs::write(path, &appended).unwrap();
+    assert_eq!(Refresh::Appended(vec![1, 2]), email_repository.refresh().unwrap());
+    assert_eq!(3, email_repository.count_emails().unwrap());
+    assert_eq!(Some("Merci Jean. A bientot.\r\n".to_string()), email_repository.get_email(&1).unwrap().body_text);
+    assert_eq!(Some("Nouveau"), email_repository.get_email(&2).unwrap().subject.as_deref());
+    assert_eq!(LexStats { messages: 3, bytes: appended.len() }, email_repository.lex_stats());
+    assert_eq!(3, MboxFile::new(path).unwrap().count_emails().unwrap());
+
+    std::fs::write(path, &content).unwrap();
+    assert_eq!(Refresh::Rewritten, email_repository.refresh().unwrap());
+    assert_eq!(Some("Re: Compte rendu"), email_repository.get_email(&1).unwrap().subject.as_deref());
+
+    // rewritten in place to the same size
+    let pos = content.windows(16).position(|window| window == b"Re: Compte rendu").unwrap();
+    let mut rewritten = content.clone();
+    rewritten[pos + 11..pos + 16].copy_from_slice(b"RENDU");
+    std::fs::write(path, &rewritten).unwrap();
+    assert_eq!(Refresh::Rewritten, email_repository.refresh().unwrap());
+    assert_eq!(Some("Re: Compte RENDU"), email_repository.get_email(&1).unwrap().subject.as_deref());
+
+    // appended bytes complete the date of the last message, which is no longer valid
+    rewritten.extend_from_slice(b"\r\nFrom c@example.com Mon Aug  4 11:56:07 2025\r\nFrom: c@example.com\r\nSubject: Date\r\nDate: Mon, 4 Aug 2025 11:56:07 +0800");
+    std::fs::write(path, &rewritten).unwrap();
+    assert_eq!(Refresh::Appended(vec![1, 2]), email_repository.refresh().unwrap());
+    rewritten.extend_from_slice(b"xyz\r\n\r\nbody\r\nFrom d@example.com Mon Aug  4 11:56:07 2025\r\nFrom: d@example.com\r\nSubject: Suivant\r\nDate: Mon, 4 Aug 2025 11:56:07 +0800\r\n\r\nbody\r\n");
+    std::fs::write(path, &rewritten).unwrap();
+    assert_eq!(Refresh::Rewritten, email_repository.refresh().unwrap());
+    assert_eq!(3, email_repository.count_emails().unwrap());
+    assert_eq!(Some("Suivant"), email_repository.get_email(&2).unwrap().subject.as_deref());
+}
+
+#[test]
+fn test_compressed_mbox_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let content = std::fs::read("datasets/test_lex.mbox").unwrap();
+    let plain = MboxFile::new("datasets/test_lex.mbox").unwrap();
+    let path = dir.path().join("compressed.mbox.zst");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, zstd::encode_all(content.as_slice(), 0).unwrap()).unwrap();
+    let cache_path = compression::plain_path_in(path, &dir.path().join("cache")).unwrap();
+    assert_ne!(path, cache_path);
+    let email_repository = MboxFile::new(&cache_path).unwrap();
+    assert_eq!(plain.count_emails().unwrap(), email_repository.count_emails().unwrap());
+    assert_eq!(plain.get_email(&0).unwrap().subject, email_repository.get_email(&0).unwrap().subject);
+}
+
+#[test]
+fn test_export_mbox_file_byte_exact() {
+    for (dataset, format) in [("datasets/dev_apisix_apache_org.mbox", MboxFormat::Mboxo), ("datasets/test_lex.mbox", MboxFormat::Mboxrd)] {
+        let email_repository = MboxFile::new(dataset).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("export-{format:?}.mbox"));
+        let path = path.to_str().unwrap();
+        let ids = [2, 0];
+        assert_eq!(2, writer::export(&email_repository, &ids, path, format).unwrap());
+        let exported = MboxFile::with_format(path, format).unwrap();
+        assert_eq!(2, exported.count_emails().unwrap());
+        for (exported_id, id) in ids.iter().enumerate() {
+            assert_eq!(email_repository.raw_email(id).unwrap(), exported.raw_email(&exported_id).unwrap());
+            assert_eq!(email_repository.get_email(id).unwrap().subject, exported.get_email(&exported_id).unwrap().subject);
+        }
+    }
+}
+
+#[test]
+fn test_export_onto_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export-source.mbox");
+    let path = path.to_str().unwrap();
+    std::fs::copy("datasets/test_lex.mbox", path).unwrap();
+    let email_repository = MboxFile::new(path).unwrap();
+    let raw = email_repository.raw_email(&2).unwrap().into_owned();
+    assert_eq!(1, writer::export(&email_repository, &[2], path, MboxFormat::Mboxrd).unwrap());
+    // the mapping of the source stays readable, the exported file replaces it
+    assert_eq!(raw, email_repository.raw_email(&2).unwrap().as_ref());
+    let exported = MboxFile::with_format(path, MboxFormat::Mboxrd).unwrap();
+    assert_eq!(1, exported.count_emails().unwrap());
+    assert_eq!(raw, exported.raw_email(&0).unwrap().as_ref());
+}
+
+#[test]
+fn test_parse_report_skipped_messages() {
+    let email_repository = MboxFile::new("datasets/test_seek_positions.mbox").unwrap();
+    let report = email_repository.parse_report();
+    assert_eq!(email_repository.lex_stats().messages - email_repository.count_emails().unwrap(), report.skipped());
+    assert!(report.issues.iter().all(|issue| issue.field.is_some()));
+    assert_eq!(report, MboxFile::new("datasets/test_seek_positions.mbox").unwrap().parse_report());
+}
+
+#[test]
+fn test_lenient_mbox_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lenient.mbox");
+    let path = path.to_str().unwrap();
+    std::fs::write(path, "From a@b.c Mon Aug  4 11:56:07 2025\nFrom: a@b.c\nDate: lundi\n\nSans objet\n").unwrap();
+    assert_eq!(0, MboxFile::new(path).unwrap().count_emails().unwrap());
+    let email_repository = MboxFile::with_mode(path, None, ParseMode::Lenient).unwrap();
+    assert_eq!(1, email_repository.count_emails().unwrap());
+    let email = email_repository.emails().next().unwrap();
+    assert_eq!(None, email.subject);
+    assert_eq!(Some("Sans objet\n".to_string()), email.body_text);
+    assert_eq!(1, email_repository.parse_report().repaired());
+    let email_repository = MboxFile::with_mode(path, Some(MboxFormat::Mboxrd), ParseMode::Lenient).unwrap();
+    assert_eq!(1, email_repository.count_emails().unwrap());
+}
+
+#[test]
+fn test_raw_email_headers_and_parts() {
+    let data = std::fs::read("datasets/test_lex.mbox").unwrap();
+    let email_repository = MboxFile::new("datasets/test_lex.mbox").unwrap();
+    let raw_email = email_repository.raw_email(&0).unwrap();
+    assert!(matches!(raw_email, Cow::Borrowed(_)));
+    assert!(raw_email.starts_with(b"Return-Path: "));
+    assert!(raw_email.ends_with(b"--000000000000444230063b7ff2f0--\n"));
+    let raw_headers = email_repository.raw_headers(&0).unwrap();
+    assert!(raw_email.starts_with(&raw_headers));
+    assert!(raw_headers.ends_with(b"boundary=\"000000000000444230063b7ff2f0\"\n"));
+    assert_eq!(raw_email, email_repository.raw_part(&0, 0).unwrap());
+    let raw_part = email_repository.raw_part(&0, 1).unwrap();
+    assert!(raw_part.starts_with(b"Content-Type: text/plain; charset=\"UTF-8\"\n"));
+    assert!(data.windows(raw_part.len()).any(|window| window == raw_part.as_ref()));
+    assert!(email_repository.raw_part(&0, 3).is_err_and(|e| e == MailboxError::PartNotFound));
+}
+
+#[test]
+fn test_email_views() {
+    for path in ["datasets/dev_apisix_apache_org.mbox", "datasets/test_emails_1000.mbox", "datasets/test_crlf.mbox"] {
+        let email_repository = MboxFile::new(path).unwrap();
+        assert_eq!(email_repository.count_emails().unwrap(), email_repository.email_views().count());
+        for (view, email) in email_repository.email_views().zip(email_repository.emails()) {
+            assert_eq!(email.id, *view.id());
+            assert_eq!(email.sender_datetime, view.datetime());
+            assert_eq!(Some(email.from.as_str()), view.from());
+            assert_eq!(email.subject.as_deref(), view.subject());
+            assert_eq!(email.body_text.as_deref(), view.body_text());
+            assert_eq!(email.body_html.as_deref(), view.body_html());
+            assert_eq!(email.subject, view.to_email().unwrap().subject);
+        }
+    }
+
+    // plain headers and bodies are read in place
+    let email_repository = MboxFile::new("datasets/test_emails.mbox").unwrap();
+    let raw_email = email_repository.raw_email(&2).unwrap();
+    let view = email_repository.email_view(&2).unwrap();
+    assert!(raw_email.as_ptr_range().contains(&view.subject().unwrap().as_ptr()));
+    assert!(raw_email.as_ptr_range().contains(&view.body_text().unwrap().as_ptr()));
+    // encoded words are decoded to an owned value
+    assert!(!raw_email.as_ptr_range().contains(&view.from().unwrap().as_ptr()));
+    assert!(email_repository.email_view(&email_repository.count_emails().unwrap()).is_err_and(|e| e == MailboxError::EmailNotFound));
+}
+
+#[test]
+fn test_threads_mailing_list() {
+    let email_repository = MboxFile::new("datasets/dev_apisix_apache_org.mbox").unwrap();
+    let forest = ThreadForest::build(&email_repository);
+    let thread = forest.thread_of(&1).unwrap();
+    assert_eq!("[VOTE] Apache apisix-ingress-controller release version 2.0.0-rc3", thread.subject);
+    assert_eq!(vec![&0, &1, &2], thread.email_ids());
+    assert_eq!(vec![&5, &6], forest.thread_of(&6).unwrap().email_ids());
+    let threads = forest.by_last_activity();
+    assert_eq!(17, threads.len());
+    assert_eq!("[VOTE] Apache apisix-ingress-controller 2.0.0-rc4", threads[0].subject);
+    assert!(threads.windows(2).all(|window| window[0].last_activity >= window[1].last_activity));
+}
+
 #[test]
 fn test_embed_sentences() {
     let embedder = InternalEmbedder::new()